@@ -0,0 +1,121 @@
+use std::str::FromStr;
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use actix_web::{get, put, web, FromRequest, HttpRequest, HttpResponse, Responder};
+use actix_web::dev::{Payload, ServiceRequest};
+use actix_web::error::{Error, ErrorForbidden, ErrorUnauthorized};
+use actix_web::http::HeaderMap;
+use futures::future::{ready, Ready};
+use crate::database::{DBManager, DatabaseStats};
+use crate::database::cache::CacheStats;
+use crate::util::compare::constant_time_eq;
+use crate::util::logging::LogLevels;
+
+/// The bearer token admin endpoints require, admin endpoints are disabled when it isn't set
+pub struct AdminToken(Option<String>);
+
+impl AdminToken {
+	pub fn new(token: Option<String>) -> Self {
+		AdminToken(token.filter(|token| !token.is_empty()))
+	}
+
+	fn matches(&self, given: &str) -> bool {
+		match &self.0 {
+			Some(token) => constant_time_eq(token.as_bytes(), given.as_bytes()),
+			None => false
+		}
+	}
+}
+
+/// Extractor that only succeeds for requests carrying the admin token
+pub struct Admin;
+
+impl FromRequest for Admin {
+	type Error = Error;
+	type Future = Ready<Result<Self, Self::Error>>;
+	type Config = ();
+
+	fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+		ready(check_admin(req).map(|_| Admin))
+	}
+}
+
+/// Whether a request carries the admin token, for endpoints that only need admin for some things
+pub fn is_admin(req: &HttpRequest) -> bool {
+	check_admin(req).is_ok()
+}
+
+/// `is_admin` for middleware, which sees the request before it's an `HttpRequest`
+pub fn is_admin_request(req: &ServiceRequest) -> bool {
+	check_token(req.app_data::<web::Data<AdminToken>>(), req.headers()).is_ok()
+}
+
+fn check_admin(req: &HttpRequest) -> Result<(), Error> {
+	check_token(req.app_data::<web::Data<AdminToken>>(), req.headers())
+}
+
+fn check_token(token: Option<&web::Data<AdminToken>>, headers: &HeaderMap) -> Result<(), Error> {
+	let token = match token {
+		Some(token) if token.0.is_some() => token,
+		_ => return Err(ErrorForbidden("Admin endpoints are disabled"))
+	};
+
+	let given = headers.get("Authorization")
+		.and_then(|header| header.to_str().ok())
+		.and_then(|header| header.strip_prefix("Bearer "));
+
+	match given {
+		Some(given) if token.matches(given) => Ok(()),
+		Some(_) => Err(ErrorForbidden("Invalid admin token")),
+		None => Err(ErrorUnauthorized("Missing admin token"))
+	}
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct Stats {
+	database: DatabaseStats,
+	cache: CacheStats
+}
+
+#[get("/stats")]
+pub async fn stats(_admin: Admin, manager: web::Data<DBManager>) -> impl Responder {
+	web::Json(Stats {
+		database: manager.stats(),
+		cache: manager.cache_stats()
+	})
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct SetLogLevel {
+	module: String,
+	/// Leaving this out puts the module back to its default level
+	level: Option<String>
+}
+
+#[get("/log-levels")]
+pub async fn log_levels(_admin: Admin, levels: web::Data<LogLevels>) -> impl Responder {
+	web::Json(levels.info())
+}
+
+/// Changes the level of a module and everything under it until the next restart
+#[put("/log-levels")]
+pub async fn set_log_level(_admin: Admin, levels: web::Data<LogLevels>, body: web::Json<SetLogLevel>) -> HttpResponse {
+	let body = body.into_inner();
+	if body.module.is_empty() {
+		return HttpResponse::BadRequest().body("module can't be empty");
+	}
+
+	let level = match body.level.as_deref().map(LevelFilter::from_str) {
+		Some(Ok(level)) => Some(level),
+		Some(Err(_)) => return HttpResponse::BadRequest().body("level must be one of off, error, warn, info, debug or trace"),
+		None => None
+	};
+
+	levels.set_module(&body.module, level);
+	match level {
+		Some(level) => warn!("Log level for {} set to {} through the admin api", body.module, level),
+		None => warn!("Log level for {} reset through the admin api", body.module)
+	}
+	HttpResponse::Ok().json(levels.info())
+}
@@ -0,0 +1,72 @@
+function element(tag, attributes, children) {
+	const el = document.createElement(tag);
+	Object.entries(attributes || {}).forEach(([key, value]) => el.setAttribute(key, value));
+	(children || []).forEach(child => el.append(child));
+	return el;
+}
+
+// Turns $refs into links to the schema further down the page
+function schemaView(schema) {
+	const pre = element("pre");
+	const json = JSON.stringify(schema, null, 2);
+	let last = 0;
+	for (const match of json.matchAll(/"#\/components\/schemas\/([^"]+)"/g)) {
+		pre.append(json.slice(last, match.index));
+		pre.append(element("a", { href: "#schema-" + match[1] }, [match[0]]));
+		last = match.index + match[0].length;
+	}
+	pre.append(json.slice(last));
+	return pre;
+}
+
+function contentView(content) {
+	return Object.entries(content || {}).map(([type, media]) =>
+		element("div", {}, [element("div", {}, [type]), media.schema ? schemaView(media.schema) : ""]));
+}
+
+function operationView(method, path, operation) {
+	const body = element("div", { class: "body" });
+	if (operation.parameters && operation.parameters.length) {
+		const rows = operation.parameters.map(param => element("tr", {}, [
+			element("td", {}, [element("code", {}, [param.name])]),
+			element("td", {}, [param.in + (param.required ? ", required" : "")]),
+			element("td", {}, [param.description || ""])
+		]));
+		body.append(element("h4", {}, ["Parameters"]), element("table", {}, rows));
+	}
+	if (operation.requestBody) {
+		body.append(element("h4", {}, ["Request body"]), ...contentView(operation.requestBody.content));
+	}
+	body.append(element("h4", {}, ["Responses"]));
+	Object.entries(operation.responses).forEach(([status, response]) => {
+		body.append(element("div", {}, [element("strong", {}, [status]), " " + response.description]), ...contentView(response.content));
+	});
+
+	const summary = element("summary", {}, [
+		element("span", { class: "method " + method }, [method]),
+		element("span", { class: "path" }, [path]),
+		operation.security ? element("span", { class: "lock", title: "Needs the admin token" }, [" \u{1F512}"]) : "",
+		" " + (operation.summary || "")
+	]);
+	return element("details", {}, [summary, body]);
+}
+
+fetch("/api/openapi.json").then(res => res.json()).then(spec => {
+	document.getElementById("title").textContent = spec.info.title + " " + spec.info.version;
+	document.getElementById("description").textContent = spec.info.description || "";
+
+	const tags = {};
+	Object.entries(spec.paths).forEach(([path, item]) => {
+		Object.entries(item).forEach(([method, operation]) => {
+			const tag = (operation.tags || ["other"])[0];
+			(tags[tag] = tags[tag] || []).push(operationView(method, path, operation));
+		});
+	});
+	const operations = document.getElementById("operations");
+	Object.entries(tags).forEach(([tag, views]) => operations.append(element("h2", {}, [tag]), ...views));
+
+	const schemas = document.getElementById("schemas");
+	Object.entries(spec.components.schemas).sort().forEach(([name, schema]) => {
+		schemas.append(element("details", { id: "schema-" + name }, [element("summary", {}, [name]), element("div", { class: "body" }, [schemaView(schema)])]));
+	});
+});
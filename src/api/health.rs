@@ -0,0 +1,32 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use actix_web::{get, web, HttpResponse, Responder};
+use crate::database::DBManager;
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct Readiness {
+	ready: bool,
+	problems: Vec<String>
+}
+
+/// The process is up and serving requests
+#[get("/healthz")]
+pub async fn healthz() -> impl Responder {
+	HttpResponse::Ok().body("ok")
+}
+
+/// CouchDB is reachable and every required database and index exists
+#[get("/readyz")]
+pub async fn readyz(manager: web::Data<DBManager>) -> impl Responder {
+	let problems = manager.check_ready().await;
+	let readiness = Readiness {
+		ready: problems.is_empty(),
+		problems
+	};
+
+	if readiness.ready {
+		HttpResponse::Ok().json(readiness)
+	} else {
+		HttpResponse::ServiceUnavailable().json(readiness)
+	}
+}
@@ -0,0 +1,78 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use actix_web::dev::HttpServiceFactory;
+use actix_web::{web, HttpRequest, HttpResponse};
+use crate::database::{DBManager, Document, DocumentType, Id};
+use crate::middleware::RequireDatabase;
+use crate::util::slug::slugify;
+use crate::util::Uuid;
+
+/// Where a set of short links point, shared with every request in its scope
+struct LinkTarget {
+	prefix: &'static str
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct ShortLink<'a, T: DocumentType> {
+	code: &'a str,
+	slug: Option<&'a str>,
+	document: Document<T>
+}
+
+/// Short links for one kind of document, `{prefix}/{code}` and `{prefix}/{code}/{slug}`.<br>
+/// The code never changes but the slug follows the name, links with an old or missing slug redirect to the current one
+pub fn short_links<T: DocumentType + 'static>(prefix: &'static str, manager: DBManager) -> impl HttpServiceFactory {
+	web::scope(prefix)
+		.wrap(RequireDatabase::new(manager))
+		.data(LinkTarget { prefix })
+		.route("/{code}", web::get().to(resolve::<T>))
+		.route("/{code}/{slug}", web::get().to(resolve::<T>))
+}
+
+async fn resolve<T: DocumentType>(req: HttpRequest, manager: web::Data<DBManager>, target: web::Data<LinkTarget>) -> HttpResponse {
+	let code = req.match_info().get("code").unwrap_or_default();
+	let id = match Uuid::from_short_code(code) {
+		Some(id) => id,
+		None => return HttpResponse::NotFound().body("Invalid link")
+	};
+
+	let doc = match manager.find_document(&Id::<T>::from(id)).await {
+		Ok(Some(doc)) => doc,
+		Ok(None) => return HttpResponse::NotFound().body("Nothing exists at this link"),
+		Err(e) => {
+			error!("Error resolving link {}/{}: {}", target.prefix, code, e);
+			return HttpResponse::InternalServerError().body("Error resolving link");
+		}
+	};
+
+	let canonical = id.to_short_code();
+	let slug = current_slug(&doc.fields);
+	if code != canonical || req.match_info().get("slug") != slug.as_deref() {
+		return HttpResponse::MovedPermanently()
+			.header("Location", link_path(target.prefix, &canonical, slug.as_deref()))
+			.finish();
+	}
+
+	HttpResponse::Ok().json(ShortLink {
+		code: &canonical,
+		slug: slug.as_deref(),
+		document: doc
+	})
+}
+
+/// The canonical short link to a document under `prefix`, `None` if its id isn't a uuid
+pub fn short_link<T: DocumentType>(prefix: &str, id: &Id<T>, fields: &T) -> Option<String> {
+	let code = id.uuid()?.to_short_code();
+	Some(link_path(prefix, &code, current_slug(fields).as_deref()))
+}
+
+fn current_slug<T: DocumentType>(fields: &T) -> Option<String> {
+	fields.name().map(slugify).filter(|slug| !slug.is_empty())
+}
+
+fn link_path(prefix: &str, code: &str, slug: Option<&str>) -> String {
+	match slug {
+		Some(slug) => format!("{}/{}/{}", prefix, code, slug),
+		None => format!("{}/{}", prefix, code)
+	}
+}
@@ -0,0 +1,14 @@
+use actix_web::{get, HttpResponse, Responder};
+use crate::util::metrics::gather;
+
+/// Every metric in the Prometheus text format
+#[get("/metrics")]
+pub async fn prometheus() -> impl Responder {
+	match gather() {
+		Ok(text) => HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(text),
+		Err(e) => {
+			error!("{}", e);
+			HttpResponse::InternalServerError().body("Error gathering metrics")
+		}
+	}
+}
@@ -1 +1,9 @@
-pub mod palettes;
\ No newline at end of file
+pub mod admin;
+pub mod health;
+pub mod links;
+pub mod metrics;
+pub mod openapi;
+pub mod palettes;
+pub mod resource;
+pub mod schema;
+pub mod versions;
@@ -0,0 +1,373 @@
+use actix_web::{get, HttpResponse};
+use schemars::JsonSchema;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use serde_json::{json, Map, Value};
+use crate::api::admin::{SetLogLevel, Stats};
+use crate::api::health::Readiness;
+use crate::api::links::ShortLink;
+use crate::api::palettes::{Batch, BatchResult, Search};
+use crate::api::resource::{Linked, Page, Resource, Revision, Saved};
+use crate::api::schema::SCHEMAS;
+use crate::api::versions::CURRENT;
+use crate::database::{MusicPack, Palette, TexturePack, ValidationErrors};
+use crate::middleware::panic::PanicResponse;
+use crate::util::logging::LogLevelsInfo;
+
+const DOCS_PAGE: &str = include_str!("docs.html");
+// Kept out of the page so it works under a `Content-Security-Policy` without `unsafe-inline`
+const DOCS_STYLE: &str = include_str!("docs.css");
+const DOCS_SCRIPT: &str = include_str!("docs.js");
+
+/// Builds up the paths and component schemas of the OpenAPI document
+struct Spec {
+	gen: SchemaGenerator,
+	paths: Map<String, Value>
+}
+
+impl Spec {
+	fn new() -> Self {
+		Spec {
+			gen: SchemaSettings::openapi3().into_generator(),
+			paths: Map::new()
+		}
+	}
+
+	/// A reference to `T`'s schema, adding it to the components
+	fn schema<T: JsonSchema>(&mut self) -> Value {
+		serde_json::to_value(self.gen.subschema_for::<T>()).unwrap()
+	}
+
+	fn operation(&mut self, method: &str, path: &str, operation: Value) {
+		let item = self.paths.entry(path.to_owned()).or_insert_with(|| json!({}));
+		item[method] = operation;
+	}
+
+	fn build(mut self) -> Value {
+		// Any route can panic
+		let internal_error = json_body("Internal server error", self.schema::<PanicResponse>());
+		for item in self.paths.values_mut() {
+			for operation in item.as_object_mut().unwrap().values_mut() {
+				operation["responses"]["500"] = internal_error.clone();
+			}
+		}
+
+		json!({
+			"openapi": "3.0.3",
+			"info": {
+				"title": "Modolumia",
+				"version": env!("CARGO_PKG_VERSION"),
+				"description": "Errors are plain text unless stated otherwise. A panic while serving a request is a 500 with an `InternalError` body. The same routes without `/v1` are deprecated and send `Deprecation` and `Sunset` headers. Browsers changing anything without an `Authorization` header have to echo the `csrf_token` cookie in `X-CSRF-Token`, or get a 403."
+			},
+			"paths": self.paths,
+			"components": {
+				"schemas": self.gen.definitions(),
+				"securitySchemes": {
+					"adminToken": {
+						"type": "http",
+						"scheme": "bearer",
+						"description": "The `ADMIN_TOKEN` the server was started with"
+					}
+				}
+			}
+		})
+	}
+}
+
+fn json_body(description: &str, schema: Value) -> Value {
+	json!({
+		"description": description,
+		"content": { "application/json": { "schema": schema } }
+	})
+}
+
+fn text(description: &str) -> Value {
+	json!({
+		"description": description,
+		"content": { "text/plain": { "schema": { "type": "string" } } }
+	})
+}
+
+fn rate_limited() -> Value {
+	text("Rate limited, `Retry-After` is how many seconds to wait")
+}
+
+fn path_param(name: &str, description: &str) -> Value {
+	json!({ "name": name, "in": "path", "required": true, "description": description, "schema": { "type": "string" } })
+}
+
+fn query_param(name: &str, description: &str, required: bool, schema: Value) -> Value {
+	json!({ "name": name, "in": "query", "required": required, "description": description, "schema": schema })
+}
+
+/// Routes outside `/api` and the ones every deployment has
+fn service_paths(spec: &mut Spec) {
+	spec.operation("get", "/healthz", json!({
+		"tags": ["health"],
+		"summary": "The process is up and serving requests",
+		"responses": { "200": text("Always `ok`") }
+	}));
+	let readiness = spec.schema::<Readiness>();
+	spec.operation("get", "/readyz", json!({
+		"tags": ["health"],
+		"summary": "CouchDB is reachable and every required database and index exists",
+		"responses": {
+			"200": json_body("Ready", readiness.clone()),
+			"503": json_body("Not ready, `problems` says why", readiness)
+		}
+	}));
+	spec.operation("get", "/metrics", json!({
+		"tags": ["health"],
+		"summary": "Prometheus metrics in the text format",
+		"responses": { "200": text("Metrics") }
+	}));
+
+	spec.operation("get", "/api/schema", json!({
+		"tags": ["schema"],
+		"summary": "The names of every JSON schema",
+		"responses": { "200": json_body("Schema names", json!({ "type": "array", "items": { "type": "string", "enum": SCHEMAS.iter().map(|(name, _)| *name).collect::<Vec<_>>() } })) }
+	}));
+	spec.operation("get", "/api/schema/{name}", json!({
+		"tags": ["schema"],
+		"summary": "A JSON schema (draft 7) for a document type or API body",
+		"parameters": [path_param("name", "One of the names from `/api/schema`")],
+		"responses": {
+			"200": { "description": "The schema", "content": { "application/schema+json": { "schema": { "type": "object" } } } },
+			"404": text("No schema with that name")
+		}
+	}));
+	spec.operation("get", "/api/openapi.json", json!({
+		"tags": ["schema"],
+		"summary": "This document",
+		"responses": { "200": json_body("OpenAPI 3 document", json!({ "type": "object" })) }
+	}));
+	spec.operation("get", "/api/docs", json!({
+		"tags": ["schema"],
+		"summary": "A page for browsing this document",
+		"responses": { "200": { "description": "HTML page", "content": { "text/html": {} } } }
+	}));
+	spec.operation("get", "/api/docs.css", json!({
+		"tags": ["schema"],
+		"summary": "Stylesheet for the docs page",
+		"responses": { "200": { "description": "Stylesheet", "content": { "text/css": {} } } }
+	}));
+	spec.operation("get", "/api/docs.js", json!({
+		"tags": ["schema"],
+		"summary": "Script for the docs page",
+		"responses": { "200": { "description": "Script", "content": { "text/javascript": {} } } }
+	}));
+}
+
+fn admin_paths(spec: &mut Spec) {
+	let auth_errors = json!({
+		"401": text("Missing admin token"),
+		"403": text("Wrong admin token, or admin endpoints are disabled")
+	});
+	let with_auth_errors = |mut responses: Value| {
+		for (status, response) in auth_errors.as_object().unwrap() {
+			responses[status] = response.clone();
+		}
+		responses
+	};
+
+	let stats = spec.schema::<Stats>();
+	spec.operation("get", "/api/v1/stats", json!({
+		"tags": ["admin"],
+		"summary": "Database document counts and read cache hit rates",
+		"security": [{ "adminToken": [] }],
+		"responses": with_auth_errors(json!({ "200": json_body("Stats", stats) }))
+	}));
+
+	let levels = spec.schema::<LogLevelsInfo>();
+	let set_level = spec.schema::<SetLogLevel>();
+	spec.operation("get", "/api/v1/admin/log-levels", json!({
+		"tags": ["admin"],
+		"summary": "The current log levels",
+		"security": [{ "adminToken": [] }],
+		"responses": with_auth_errors(json!({ "200": json_body("Log levels", levels.clone()) }))
+	}));
+	spec.operation("put", "/api/v1/admin/log-levels", json!({
+		"tags": ["admin"],
+		"summary": "Changes the level of a module and everything under it until the next restart",
+		"security": [{ "adminToken": [] }],
+		"requestBody": { "required": true, "content": { "application/json": { "schema": set_level } } },
+		"responses": with_auth_errors(json!({
+			"200": json_body("The log levels after the change", levels),
+			"400": text("Empty module or unknown level")
+		}))
+	}));
+}
+
+fn palette_paths(spec: &mut Spec) {
+	let search = spec.schema::<Search>();
+	let palettes = spec.schema::<Vec<Palette>>();
+	spec.operation("post", "/api/v1/palettes/search", json!({
+		"tags": ["palettes"],
+		"summary": "Palettes whose name or author matches a regex",
+		"requestBody": { "required": true, "content": { "application/json": { "schema": search } } },
+		"responses": {
+			"200": json_body("Matching palettes, or a message when nothing matched", palettes),
+			"429": rate_limited()
+		}
+	}));
+
+	let batch = spec.schema::<Batch>();
+	let result = spec.schema::<BatchResult>();
+	spec.operation("post", "/api/v1/palettes/batch", json!({
+		"tags": ["palettes"],
+		"summary": "Up to 100 palettes by id, ids that don't exist are listed in `missing`",
+		"requestBody": { "required": true, "content": { "application/json": { "schema": batch } } },
+		"responses": {
+			"200": json_body("The palettes that exist, and the ids that don't or couldn't be read", result),
+			"400": text("More than 100 ids")
+		}
+	}));
+}
+
+/// The routes `resource::resource` mounts for `T`
+fn resource_paths<T: Resource>(spec: &mut Spec) {
+	let tag = T::PATH.trim_start_matches('/');
+	let collection = format!("{}{}", CURRENT, T::PATH);
+	let item = format!("{}{}/{{id}}", CURRENT, T::PATH);
+
+	let page = spec.schema::<Page<T>>();
+	let document = spec.schema::<Linked<T>>();
+	let fields = spec.schema::<T>();
+	let revision = spec.schema::<Revision<T>>();
+	let saved = spec.schema::<Saved<'static, T>>();
+	let invalid = json_body("Fields that broke a rule", spec.schema::<ValidationErrors>());
+	let unavailable = text("The database is unavailable");
+
+	let mut parameters: Vec<Value> = T::SEARCH_FIELDS.iter()
+		.map(|field| query_param(field, "Only documents where this field is exactly this value", false, json!({ "type": "string" })))
+		.collect();
+	parameters.push(query_param("limit", "Page size, at most 100", false, json!({ "type": "integer", "minimum": 1, "maximum": 100, "default": 25 })));
+	parameters.push(query_param("bookmark", "The `bookmark` of the previous page", false, json!({ "type": "string" })));
+	spec.operation("get", &collection, json!({
+		"tags": [tag],
+		"summary": "Searches on the whitelisted fields, a page at a time",
+		"parameters": parameters,
+		"responses": {
+			"200": json_body("A page of documents, pass `bookmark` back for the next one", page),
+			"400": text("Bad limit or a field that can't be searched on"),
+			"429": rate_limited(),
+			"503": unavailable
+		}
+	}));
+	spec.operation("post", &collection, json!({
+		"tags": [tag],
+		"summary": "Creates a document",
+		"security": [{ "adminToken": [] }],
+		"requestBody": { "required": true, "content": { "application/json": { "schema": fields } } },
+		"responses": {
+			"201": json_body("Created, `Location` is the new document", saved.clone()),
+			"403": text("Not allowed to create documents"),
+			"422": invalid,
+			"429": rate_limited(),
+			"503": unavailable
+		}
+	}));
+
+	let id = path_param("id", "The document id");
+	spec.operation("get", &item, json!({
+		"tags": [tag],
+		"summary": "Gets a document",
+		"parameters": [id],
+		"responses": {
+			"200": json_body("The document and its short link", document),
+			"404": text("No document with that id"),
+			"503": unavailable
+		}
+	}));
+	spec.operation("put", &item, json!({
+		"tags": [tag],
+		"summary": "Replaces a document, `_rev` has to be its current revision",
+		"security": [{ "adminToken": [] }],
+		"parameters": [id],
+		"requestBody": { "required": true, "content": { "application/json": { "schema": revision } } },
+		"responses": {
+			"200": json_body("Updated, `rev` is the new revision", saved.clone()),
+			"403": text("Not allowed to change this document"),
+			"404": text("No document with that id"),
+			"409": text("`_rev` isn't the current revision"),
+			"422": invalid,
+			"429": rate_limited(),
+			"503": unavailable
+		}
+	}));
+	spec.operation("delete", &item, json!({
+		"tags": [tag],
+		"summary": "Deletes a document",
+		"security": [{ "adminToken": [] }],
+		"parameters": [id, query_param("rev", "The current revision", true, json!({ "type": "string" }))],
+		"responses": {
+			"200": json_body("Deleted", saved),
+			"403": text("Not allowed to delete this document"),
+			"404": text("No document with that id"),
+			"409": text("`rev` isn't the current revision"),
+			"503": unavailable
+		}
+	}));
+}
+
+/// The routes `links::short_links` mounts for `T`
+fn link_paths<T: Resource>(spec: &mut Spec, tag: &str) {
+	let prefix = T::SHORT_LINK;
+	let link = spec.schema::<ShortLink<'static, T>>();
+	let responses = json!({
+		"200": json_body("The document behind the link", link),
+		"301": { "description": "The code or slug isn't the canonical one, `Location` is" },
+		"404": text("Invalid link or nothing exists at it"),
+		"503": text("The database is unavailable")
+	});
+
+	let code = path_param("code", "The short code");
+	spec.operation("get", &format!("{}/{{code}}", prefix), json!({
+		"tags": [tag],
+		"summary": "Resolves a short link",
+		"parameters": [code],
+		"responses": responses
+	}));
+	spec.operation("get", &format!("{}/{{code}}/{{slug}}", prefix), json!({
+		"tags": [tag],
+		"summary": "Resolves a short link with a slug of the name",
+		"parameters": [code, path_param("slug", "The name as a slug")],
+		"responses": responses
+	}));
+}
+
+/// The OpenAPI document for every route the server has
+pub fn document() -> Value {
+	let mut spec = Spec::new();
+	service_paths(&mut spec);
+	admin_paths(&mut spec);
+	palette_paths(&mut spec);
+	resource_paths::<Palette>(&mut spec);
+	resource_paths::<MusicPack>(&mut spec);
+	resource_paths::<TexturePack>(&mut spec);
+	link_paths::<Palette>(&mut spec, "links");
+	link_paths::<MusicPack>(&mut spec, "links");
+	link_paths::<TexturePack>(&mut spec, "links");
+
+	spec.build()
+}
+
+#[get("/api/openapi.json")]
+pub async fn openapi() -> HttpResponse {
+	HttpResponse::Ok().json(document())
+}
+
+/// Renders `/api/openapi.json` without loading anything from other sites
+#[get("/api/docs")]
+pub async fn docs() -> HttpResponse {
+	HttpResponse::Ok().content_type("text/html; charset=utf-8").body(DOCS_PAGE)
+}
+
+#[get("/api/docs.css")]
+pub async fn docs_style() -> HttpResponse {
+	HttpResponse::Ok().content_type("text/css; charset=utf-8").body(DOCS_STYLE)
+}
+
+#[get("/api/docs.js")]
+pub async fn docs_script() -> HttpResponse {
+	HttpResponse::Ok().content_type("text/javascript; charset=utf-8").body(DOCS_SCRIPT)
+}
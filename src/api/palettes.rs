@@ -1,24 +1,32 @@
-use serde::Deserialize;
-use actix_web::{post, get, web, Responder};
-use crate::database::{SearchTerm, SearchBuilder, Databases};
-use crate::database::Palette;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use actix_web::{post, web, HttpResponse, Responder};
+use crate::database::{SearchTerm, SearchBuilder};
+use crate::database::{Palette, Document, Id};
 use crate::database::{DBManager};
 
-#[derive(Deserialize, Debug)]
+const MAX_BATCH_SIZE: usize = 100;
+
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct Search {
 	search_val: String
 }
 
-#[post("/api/palettes/search")]
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct Batch {
+	ids: Vec<Id<Palette>>
+}
+
+/// Mounted by `api::versions` so it can be rate limited
 pub async fn search(manager: web::Data<DBManager>, web::Json(search_term_str): web::Json<Search>) -> impl Responder {
 	debug!("{:?}", search_term_str);
 	let search_term = SearchTerm::or()
 		.child(SearchTerm::string("name")
-			.child(SearchTerm::regex().child(SearchTerm::string(&format!("{}", search_term_str.search_val)))))
+			.child(SearchTerm::regex().child(SearchTerm::string(&search_term_str.search_val))))
 		.child(SearchTerm::string("author")
-			.child(SearchTerm::regex().child(SearchTerm::string(&format!("{}", search_term_str.search_val)))));
+			.child(SearchTerm::regex().child(SearchTerm::string(&search_term_str.search_val))));
 	let search = SearchBuilder::new().filter(search_term).build();
-	let search_res = manager.search_db::<Palette>(Databases::Palettes, search).await;
+	let search_res = manager.search_db::<Palette>(search).await;
 	match search_res {
 		Ok(val) => {
 			match val.docs {
@@ -36,5 +44,42 @@ pub async fn search(manager: web::Data<DBManager>, web::Json(search_term_str): w
 	}
 }
 
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct BatchResult {
+	docs: Vec<Document<Palette>>,
+	/// Ids that don't exist or were deleted
+	missing: Vec<Id<Palette>>,
+	/// Ids CouchDB had an error getting
+	failed: Vec<Id<Palette>>
+}
+
+#[post("/palettes/batch")]
+pub async fn batch(manager: web::Data<DBManager>, web::Json(batch): web::Json<Batch>) -> HttpResponse {
+	if batch.ids.len() > MAX_BATCH_SIZE {
+		return HttpResponse::BadRequest().body(format!("Can only get {} palettes at once", MAX_BATCH_SIZE));
+	}
+
+	let results = match manager.get_documents::<Palette>(&batch.ids).await {
+		Ok(results) => results,
+		Err(e) => {
+			error!("Error getting palettes: {}", e);
+			return HttpResponse::InternalServerError().body("Error getting palettes");
+		}
+	};
+
+	let mut output = BatchResult { docs: Vec::new(), missing: Vec::new(), failed: Vec::new() };
+	for (id, result) in batch.ids.into_iter().zip(results) {
+		match result {
+			Ok(Some(doc)) => output.docs.push(doc),
+			Ok(None) => output.missing.push(id),
+			Err(e) => {
+				error!("Error getting palette {} in a batch: {}", id, e);
+				output.failed.push(id);
+			}
+		}
+	}
+	HttpResponse::Ok().json(output)
+}
+
 // #[get("/palettes/{id}")]
 // pub async fn get_palette(manager: web::Data<DBManager>, web::Url(search_term_str))
\ No newline at end of file
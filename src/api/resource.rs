@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use schemars::JsonSchema;
+use actix_web::{guard, web, HttpRequest, HttpResponse, Scope};
+use crate::api::admin::is_admin;
+use crate::api::links::short_link;
+use crate::database::{DBManager, Document, DocumentType, Id, SaveError, SearchBuilder, SearchTerm};
+use crate::database::{MusicPack, Palette, TexturePack};
+use crate::middleware::{RateLimits, RequireCsrf};
+use crate::util::Uuid;
+
+const DEFAULT_PAGE_SIZE: u32 = 25;
+const MAX_PAGE_SIZE: u32 = 100;
+
+/// A `DocumentType` served as a standard REST resource, see `resource`
+pub trait Resource: DocumentType + 'static {
+	/// Where the resource is mounted, relative to the scope it's added to
+	const PATH: &'static str;
+	/// Fields that can be searched on, any other query parameter is rejected
+	const SEARCH_FIELDS: &'static [&'static str];
+	/// Where `links::short_links` are mounted for the resource
+	const SHORT_LINK: &'static str;
+
+	/// Whether `requester` may do `action`.<br>
+	/// `doc` is the submitted document for creates and the stored one for reads, updates and deletes.
+	/// Until there are accounts only admins can change anything
+	fn authorize(requester: &Requester, action: Action, _doc: &Self) -> bool {
+		action == Action::Read || requester.admin
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+	Read,
+	Create,
+	Update,
+	Delete
+}
+
+/// Who is making a request, for `Resource::authorize`
+pub struct Requester<'a> {
+	pub admin: bool,
+	pub request: &'a HttpRequest
+}
+
+impl<'a> Requester<'a> {
+	fn new(request: &'a HttpRequest) -> Self {
+		Requester {
+			admin: is_admin(request),
+			request
+		}
+	}
+}
+
+/// The body of an update, the fields plus the `_rev` being replaced
+#[derive(Deserialize, JsonSchema)]
+#[serde(bound(deserialize = "T: DeserializeOwned"))]
+pub struct Revision<T> {
+	_rev: String,
+	#[serde(flatten)]
+	fields: T
+}
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+	rev: String
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(bound(serialize = "T: Serialize"))]
+pub struct Page<T> {
+	docs: Vec<Linked<T>>,
+	bookmark: Option<String>
+}
+
+/// A document and the short link to share it with
+#[derive(Serialize, JsonSchema)]
+#[serde(bound(serialize = "T: Serialize"))]
+pub struct Linked<T> {
+	#[serde(flatten)]
+	document: Document<T>,
+	/// `null` for documents whose id isn't a uuid
+	short_link: Option<String>
+}
+
+impl<T: Resource> Linked<T> {
+	fn new(document: Document<T>) -> Self {
+		Linked {
+			short_link: short_link(T::SHORT_LINK, &document._id, &document.fields),
+			document
+		}
+	}
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(bound(serialize = ""))]
+pub struct Saved<'a, T> {
+	id: &'a Id<T>,
+	rev: Option<&'a str>,
+	/// `null` once the document is deleted
+	short_link: Option<String>
+}
+
+/// The standard routes for a resource, mounted at `T::PATH`:<br>
+/// `GET /` searches the whitelisted fields given as query parameters, paged with `limit` and `bookmark`<br>
+/// `POST /` creates, `GET /{id}` reads, `PUT /{id}` updates with the current `_rev` and `DELETE /{id}?rev=` deletes.<br>
+/// Documents that fail `Validate` get a 422 listing every broken field
+/// Searches count towards the `search` rate limit, creates and updates towards `uploads`
+pub fn resource<T: Resource>(limits: &RateLimits, csrf: &RequireCsrf) -> Scope {
+	web::scope(T::PATH)
+		.service(web::resource("").guard(guard::Get()).to(list::<T>).wrap(limits.search.clone()))
+		.service(web::resource("").guard(guard::Post()).to(create::<T>).wrap(limits.uploads.clone()).wrap(csrf.clone()))
+		.service(web::resource("/{id}").guard(guard::Put()).to(update::<T>).wrap(limits.uploads.clone()).wrap(csrf.clone()))
+		.service(web::resource("/{id}").guard(guard::Delete()).to(delete::<T>).wrap(csrf.clone()))
+		.route("/{id}", web::get().to(get::<T>))
+}
+
+async fn list<T: Resource>(manager: web::Data<DBManager>, web::Query(mut query): web::Query<HashMap<String, String>>) -> HttpResponse {
+	let limit = match query.remove("limit").map(|limit| limit.parse::<u32>()) {
+		Some(Ok(limit)) if limit > 0 => limit.min(MAX_PAGE_SIZE),
+		Some(_) => return HttpResponse::BadRequest().body("limit has to be a positive number"),
+		None => DEFAULT_PAGE_SIZE
+	};
+	let bookmark = query.remove("bookmark");
+
+	let mut fields: Vec<(String, String)> = query.into_iter().collect();
+	if let Some((field, _)) = fields.iter().find(|(field, _)| !T::SEARCH_FIELDS.contains(&field.as_str())) {
+		return HttpResponse::BadRequest().body(format!("Can't search on {}, searchable fields are: {}", field, T::SEARCH_FIELDS.join(", ")));
+	}
+	// Sorted so the same search is the same selector, which keeps the search cache useful
+	fields.sort();
+
+	let filter = if fields.is_empty() {
+		// Mango needs a selector, this one matches every document
+		SearchTerm::string("_id").child(SearchTerm::gt().child(SearchTerm::null()))
+	} else {
+		fields.iter().fold(SearchTerm::and(), |filter, (field, value)| filter.child(SearchTerm::pair(field, value)))
+	};
+
+	let mut search = SearchBuilder::new().filter(filter).limit(limit);
+	if let Some(bookmark) = bookmark {
+		search = search.bookmark(bookmark);
+	}
+
+	match manager.search_db::<T>(search.build()).await {
+		Ok(res) => HttpResponse::Ok().json(Page {
+			docs: res.docs.unwrap_or_default().into_iter().map(Linked::new).collect(),
+			bookmark: res.bookmark
+		}),
+		Err(e) => {
+			error!("Error searching {}: {}", T::DATABASE.to_string(), e);
+			HttpResponse::InternalServerError().body("Error searching")
+		}
+	}
+}
+
+async fn get<T: Resource>(req: HttpRequest, manager: web::Data<DBManager>) -> HttpResponse {
+	let doc = match find::<T>(&req, &manager).await {
+		Ok(doc) => doc,
+		Err(res) => return res
+	};
+
+	if !T::authorize(&Requester::new(&req), Action::Read, &doc.fields) {
+		return HttpResponse::Forbidden().body("Not allowed to read this document");
+	}
+
+	HttpResponse::Ok().json(Linked::new(doc))
+}
+
+async fn create<T: Resource>(req: HttpRequest, manager: web::Data<DBManager>, web::Json(fields): web::Json<T>) -> HttpResponse {
+	if !T::authorize(&Requester::new(&req), Action::Create, &fields) {
+		return HttpResponse::Forbidden().body("Not allowed to create documents here");
+	}
+	if let Err(errors) = fields.validate() {
+		return HttpResponse::UnprocessableEntity().json(errors);
+	}
+
+	let id = Id::from(Uuid::time_ordered());
+	let link = short_link(T::SHORT_LINK, &id, &fields);
+	match manager.create_doc_with_id(id, fields).await {
+		Ok(id) => HttpResponse::Created()
+			.header("Location", format!("{}/{}", req.path().trim_end_matches('/'), id))
+			.json(Saved { id: &id, rev: None, short_link: link }),
+		Err(e) => {
+			error!("Error creating document in {}: {}", T::DATABASE.to_string(), e);
+			HttpResponse::InternalServerError().body("Error creating document")
+		}
+	}
+}
+
+async fn update<T: Resource>(req: HttpRequest, manager: web::Data<DBManager>, web::Json(revision): web::Json<Revision<T>>) -> HttpResponse {
+	let existing = match find::<T>(&req, &manager).await {
+		Ok(doc) => doc,
+		Err(res) => return res
+	};
+
+	if !T::authorize(&Requester::new(&req), Action::Update, &existing.fields) {
+		return HttpResponse::Forbidden().body("Not allowed to change this document");
+	}
+	if let Err(errors) = revision.fields.validate() {
+		return HttpResponse::UnprocessableEntity().json(errors);
+	}
+
+	let doc = Document {
+		_id: existing._id,
+		_rev: Some(revision._rev),
+		_attachments: None,
+		fields: revision.fields
+	};
+	match manager.update_doc(&doc).await {
+		Ok(rev) => HttpResponse::Ok().json(Saved { id: &doc._id, rev: Some(&rev), short_link: short_link(T::SHORT_LINK, &doc._id, &doc.fields) }),
+		// CouchDB checks the revision, `existing` may have come from the cache
+		Err(SaveError::Conflict) => HttpResponse::Conflict().body("The document was changed since that revision"),
+		Err(e) => {
+			error!("Error updating {} in {}: {}", doc._id, T::DATABASE.to_string(), e);
+			HttpResponse::InternalServerError().body("Error updating document")
+		}
+	}
+}
+
+async fn delete<T: Resource>(req: HttpRequest, manager: web::Data<DBManager>, web::Query(query): web::Query<DeleteQuery>) -> HttpResponse {
+	let existing = match find::<T>(&req, &manager).await {
+		Ok(doc) => doc,
+		Err(res) => return res
+	};
+
+	if !T::authorize(&Requester::new(&req), Action::Delete, &existing.fields) {
+		return HttpResponse::Forbidden().body("Not allowed to delete this document");
+	}
+
+	match manager.delete_doc(&existing._id, &query.rev).await {
+		Ok(rev) => HttpResponse::Ok().json(Saved { id: &existing._id, rev: Some(&rev), short_link: None }),
+		Err(SaveError::Conflict) => HttpResponse::Conflict().body("The document was changed since that revision"),
+		Err(e) => {
+			error!("Error deleting {} in {}: {}", existing._id, T::DATABASE.to_string(), e);
+			HttpResponse::InternalServerError().body("Error deleting document")
+		}
+	}
+}
+
+/// The document named by the `{id}` in the path, or the response to send when there isn't one
+async fn find<T: Resource>(req: &HttpRequest, manager: &DBManager) -> Result<Document<T>, HttpResponse> {
+	let id = Id::<T>::new(req.match_info().get("id").unwrap_or_default().to_owned());
+	// Ids starting with _ are CouchDB's own endpoints and design documents, the path has already been
+	// percent-decoded so an id could also hold a / ? or # that ends up in CouchDB's url
+	if id.as_str().is_empty() || id.as_str().starts_with('_') || id.as_str().contains(&['/', '?', '#'][..]) {
+		return Err(HttpResponse::NotFound().body("Document not found"));
+	}
+
+	match manager.find_document(&id).await {
+		Ok(Some(doc)) => Ok(doc),
+		Ok(None) => Err(HttpResponse::NotFound().body("Document not found")),
+		Err(e) => {
+			error!("Error getting {} from {}: {}", id, T::DATABASE.to_string(), e);
+			Err(HttpResponse::InternalServerError().body("Error getting document"))
+		}
+	}
+}
+
+impl Resource for Palette {
+	const PATH: &'static str = "/palettes";
+	const SEARCH_FIELDS: &'static [&'static str] = &["name", "author"];
+	const SHORT_LINK: &'static str = "/p";
+}
+
+impl Resource for MusicPack {
+	const PATH: &'static str = "/music-packs";
+	const SEARCH_FIELDS: &'static [&'static str] = &["name", "author"];
+	const SHORT_LINK: &'static str = "/m";
+}
+
+impl Resource for TexturePack {
+	const PATH: &'static str = "/texture-packs";
+	const SEARCH_FIELDS: &'static [&'static str] = &["name", "author"];
+	const SHORT_LINK: &'static str = "/t";
+}
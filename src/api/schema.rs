@@ -0,0 +1,64 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use schemars::JsonSchema;
+use schemars::gen::SchemaSettings;
+use schemars::schema::RootSchema;
+use crate::api::admin::{SetLogLevel, Stats};
+use crate::api::health::Readiness;
+use crate::api::links::ShortLink;
+use crate::api::palettes::{Batch, BatchResult, Search};
+use crate::api::resource::{Linked, Page, Revision, Saved};
+use crate::database::{Account, MusicPack, Palette, TexturePack, ValidationErrors};
+use crate::util::logging::LogLevelsInfo;
+
+type SchemaFn = fn() -> RootSchema;
+
+/// Every schema served at `/api/schema/{name}`, generated from the structs so they can't drift apart
+pub const SCHEMAS: &[(&str, SchemaFn)] = &[
+	// Document types
+	("palette", schema::<Palette>),
+	("music-pack", schema::<MusicPack>),
+	("texture-pack", schema::<TexturePack>),
+	("account", schema::<Account>),
+	// Resource bodies
+	("palette-document", schema::<Linked<Palette>>),
+	("music-pack-document", schema::<Linked<MusicPack>>),
+	("texture-pack-document", schema::<Linked<TexturePack>>),
+	("palette-update", schema::<Revision<Palette>>),
+	("music-pack-update", schema::<Revision<MusicPack>>),
+	("texture-pack-update", schema::<Revision<TexturePack>>),
+	("palette-page", schema::<Page<Palette>>),
+	("music-pack-page", schema::<Page<MusicPack>>),
+	("texture-pack-page", schema::<Page<TexturePack>>),
+	("saved", schema::<Saved<'static, Palette>>),
+	("validation-errors", schema::<ValidationErrors>),
+	// Other endpoints
+	("palette-search", schema::<Search>),
+	("palette-batch", schema::<Batch>),
+	("palette-batch-result", schema::<BatchResult>),
+	("palette-link", schema::<ShortLink<'static, Palette>>),
+	("music-pack-link", schema::<ShortLink<'static, MusicPack>>),
+	("texture-pack-link", schema::<ShortLink<'static, TexturePack>>),
+	("readiness", schema::<Readiness>),
+	("stats", schema::<Stats>),
+	("log-levels", schema::<LogLevelsInfo>),
+	("set-log-level", schema::<SetLogLevel>)
+];
+
+/// Draft 7 is the newest draft most validators understand
+pub fn schema<T: JsonSchema>() -> RootSchema {
+	SchemaSettings::draft07().into_generator().into_root_schema_for::<T>()
+}
+
+/// The names of every schema
+#[get("")]
+pub async fn list() -> impl Responder {
+	web::Json(SCHEMAS.iter().map(|(name, _)| *name).collect::<Vec<_>>())
+}
+
+#[get("/{name}")]
+pub async fn get(name: web::Path<String>) -> HttpResponse {
+	match SCHEMAS.iter().find(|(schema_name, _)| *schema_name == name.as_str()) {
+		Some((_, schema)) => HttpResponse::Ok().content_type("application/schema+json").json(schema()),
+		None => HttpResponse::NotFound().body(format!("No schema named {}", name))
+	}
+}
@@ -0,0 +1,72 @@
+use actix_web::web;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use crate::api::{admin, palettes, resource};
+use crate::database::{DBManager, MusicPack, Palette, TexturePack};
+use crate::middleware::{Deprecated, RateLimits, RequireCsrf, RequireDatabase};
+
+/// A version of the API and where it's mounted.<br>
+/// A new version gets its own `routes` that can reuse the handlers of the last one and only swap out what changed,
+/// the old version keeps being served next to it until its sunset
+pub struct ApiVersion {
+	pub path: &'static str,
+	pub routes: fn(&mut web::ServiceConfig, &DBManager, &RateLimits, &RequireCsrf),
+	/// `YYYY-MM-DD`, deprecated versions send `Deprecation` and `Sunset` headers
+	pub deprecated: Option<&'static str>,
+	pub sunset: Option<&'static str>,
+	/// The `path` of the version replacing this one, each route links the same route there
+	pub successor: Option<&'static str>
+}
+
+pub const CURRENT: &str = "/api/v1";
+
+pub const VERSIONS: &[ApiVersion] = &[
+	ApiVersion { path: "/api/v1", routes: v1, deprecated: None, sunset: None, successor: None },
+	// The routes from before versioning, kept so existing clients don't break.
+	// Has to come after the other versions since it would match their paths too
+	ApiVersion { path: "/api", routes: v1, deprecated: Some("2026-10-19"), sunset: Some("2027-04-19"), successor: Some(CURRENT) }
+];
+
+/// Mounts every version in `VERSIONS`
+pub fn configure(cfg: &mut web::ServiceConfig, manager: &DBManager, limits: &RateLimits, csrf: &RequireCsrf) {
+	for version in VERSIONS {
+		let scope = web::scope(version.path).configure(|cfg| (version.routes)(cfg, manager, limits, csrf));
+
+		match version.deprecated {
+			Some(since) => {
+				let mut deprecated = Deprecated::new(date(since));
+				if let Some(sunset) = version.sunset {
+					deprecated = deprecated.sunset(date(sunset));
+				}
+				if let Some(successor) = version.successor {
+					deprecated = deprecated.successor(version.path, successor);
+				}
+				cfg.service(scope.wrap(deprecated));
+			},
+			None => {
+				cfg.service(scope);
+			}
+		}
+	}
+}
+
+fn date(date: &str) -> DateTime<Utc> {
+	let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").expect("API version dates are YYYY-MM-DD");
+	Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+}
+
+fn v1(cfg: &mut web::ServiceConfig, manager: &DBManager, limits: &RateLimits, csrf: &RequireCsrf) {
+	// Admin endpoints that don't need the database, so they still work while it's down.
+	// They have to come before the catch-all scope below, it would answer their paths with a 503
+	cfg.service(admin::stats)
+		.service(web::scope("/admin")
+			.wrap(csrf.clone())
+			.service(admin::log_levels)
+			.service(admin::set_log_level))
+		.service(web::scope("")
+			.wrap(RequireDatabase::new(manager.clone()))
+			.service(web::resource("/palettes/search").route(web::post().to(palettes::search)).wrap(limits.search.clone()))
+			.service(palettes::batch)
+			.service(resource::resource::<Palette>(limits, csrf))
+			.service(resource::resource::<MusicPack>(limits, csrf))
+			.service(resource::resource::<TexturePack>(limits, csrf)));
+}
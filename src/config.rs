@@ -0,0 +1,658 @@
+use dotenv::dotenv;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use log::LevelFilter;
+use serde::Deserialize;
+
+const DEFAULT_CONFIG_PATH: &str = "modolumia.toml";
+
+/// Server configuration, read from a TOML file and then overridden by environment variables.<br>
+/// The file is `modolumia.toml` unless `MODOLUMIA_CONFIG` points somewhere else, and may be missing
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	pub server: ServerConfig,
+	pub database: DatabaseConfig,
+	pub static_files: StaticConfig,
+	pub uploads: UploadConfig,
+	pub logging: LogConfig,
+	pub rate_limits: RateLimitConfig,
+	pub security: SecurityConfig,
+	pub cors: CorsConfig
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+	/// Addresses to listen on, like `127.0.0.1:3000`
+	pub listen: Vec<String>,
+	/// Worker threads, defaults to one per CPU core
+	pub workers: Option<usize>,
+	/// Bearer token for admin endpoints, they are disabled when it isn't set
+	pub admin_token: Option<String>,
+	/// How long requests get to finish after a SIGTERM before they are cut off
+	pub shutdown_timeout_secs: u64
+}
+
+impl Default for ServerConfig {
+	fn default() -> Self {
+		ServerConfig {
+			listen: vec!["127.0.0.1:3000".to_owned()],
+			workers: None,
+			admin_token: None,
+			shutdown_timeout_secs: 30
+		}
+	}
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+	pub url: String,
+	/// Put in front of every database name, `modolumia` gives `modolumia_palettes`
+	pub name_prefix: String,
+	/// Put after every database name to keep environments apart, `staging` gives `modolumia_palettes_staging`.<br>
+	/// Debug builds default to `testing`
+	pub namespace: Option<String>,
+	/// `basic`, `session` or `jwt`
+	pub auth_mode: String,
+	/// `username:password` for `basic` and `session` auth
+	pub authorization: Option<String>,
+	pub jwt: Option<String>,
+	pub ca_bundle: Option<PathBuf>,
+	pub client_cert: Option<PathBuf>,
+	pub client_key: Option<PathBuf>,
+	/// Skip TLS certificate checks, rejected outside of debug builds
+	pub tls_insecure: bool,
+	pub connect_timeout_secs: u64,
+	pub read_timeout_secs: u64,
+	pub retries: u32,
+	pub cache_documents: usize,
+	pub cache_searches: usize,
+	pub cache_ttl_secs: u64
+}
+
+impl Default for DatabaseConfig {
+	fn default() -> Self {
+		DatabaseConfig {
+			url: String::new(),
+			name_prefix: "modolumia".to_owned(),
+			namespace: if cfg!(debug_assertions) { Some("testing".to_owned()) } else { None },
+			auth_mode: "basic".to_owned(),
+			authorization: None,
+			jwt: None,
+			ca_bundle: None,
+			client_cert: None,
+			client_key: None,
+			tls_insecure: false,
+			connect_timeout_secs: 5,
+			read_timeout_secs: 30,
+			retries: 3,
+			cache_documents: 1024,
+			cache_searches: 256,
+			cache_ttl_secs: 60
+		}
+	}
+}
+
+impl DatabaseConfig {
+	pub fn connect_timeout(&self) -> Duration {
+		Duration::from_secs(self.connect_timeout_secs)
+	}
+
+	pub fn read_timeout(&self) -> Duration {
+		Duration::from_secs(self.read_timeout_secs)
+	}
+
+	pub fn cache_ttl(&self) -> Duration {
+		Duration::from_secs(self.cache_ttl_secs)
+	}
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StaticConfig {
+	/// Served under `/resources`
+	pub resources_dir: PathBuf,
+	/// The built frontend, served under `/`
+	pub html_dir: PathBuf
+}
+
+impl Default for StaticConfig {
+	fn default() -> Self {
+		StaticConfig {
+			resources_dir: PathBuf::from("resources"),
+			html_dir: PathBuf::from("html")
+		}
+	}
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+	/// Largest JSON request body in bytes
+	pub max_json_size: usize,
+	/// Largest raw request body, like a music pack upload, in bytes
+	pub max_file_size: usize
+}
+
+impl Default for UploadConfig {
+	fn default() -> Self {
+		UploadConfig {
+			max_json_size: 64 * 1024,
+			max_file_size: 32 * 1024 * 1024
+		}
+	}
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+	pub enabled: bool,
+	/// Take the client ip from `X-Forwarded-For`, only turn on behind a proxy that sets it
+	pub trust_proxy: bool,
+	/// Searches, which run a regex query in CouchDB
+	pub search: LimitConfig,
+	/// Creating and updating documents
+	pub uploads: LimitConfig,
+	/// Submitting highscores and speedruns
+	pub scores: LimitConfig,
+	/// Logging in, keep it keyed by ip since there's no token before logging in
+	pub login: LimitConfig
+}
+
+impl Default for RateLimitConfig {
+	fn default() -> Self {
+		RateLimitConfig {
+			enabled: true,
+			trust_proxy: false,
+			search: LimitConfig {
+				burst: 20,
+				per_minute: 30,
+				key: "ip".to_owned()
+			},
+			uploads: LimitConfig {
+				burst: 5,
+				per_minute: 10,
+				key: "token".to_owned()
+			},
+			scores: LimitConfig {
+				burst: 10,
+				per_minute: 20,
+				key: "ip".to_owned()
+			},
+			login: LimitConfig {
+				burst: 5,
+				per_minute: 5,
+				key: "ip".to_owned()
+			}
+		}
+	}
+}
+
+/// A token bucket, it holds `burst` requests and refills at `per_minute`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LimitConfig {
+	pub burst: u32,
+	pub per_minute: u32,
+	/// What requests are counted by, `ip` or `token` (a valid `Authorization` header, falling back to the ip without one).<br>
+	/// `account` is reserved for when there are accounts
+	pub key: String
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityConfig {
+	/// Sent on every response that doesn't set its own, empty to not send one
+	pub content_security_policy: String,
+	/// `Strict-Transport-Security` max-age, 0 to not send it. Debug builds default to 0
+	pub hsts_max_age_secs: u64,
+	pub referrer_policy: String,
+	/// Double-submit CSRF checks on routes that change things
+	pub csrf: bool
+}
+
+impl Default for SecurityConfig {
+	fn default() -> Self {
+		SecurityConfig {
+			content_security_policy: "default-src 'self'; img-src 'self' data:; object-src 'none'; base-uri 'self'; form-action 'self'; frame-ancestors 'none'".to_owned(),
+			hsts_max_age_secs: if cfg!(debug_assertions) { 0 } else { 365 * 24 * 60 * 60 },
+			referrer_policy: "strict-origin-when-cross-origin".to_owned(),
+			csrf: true
+		}
+	}
+}
+
+/// Which other sites browsers let call the API
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+	/// Origins like `https://example.com`, `*` for any. Empty turns CORS off
+	pub allowed_origins: Vec<String>,
+	pub allowed_methods: Vec<String>,
+	/// Request headers other sites may send besides the ones browsers always allow
+	pub allowed_headers: Vec<String>,
+	/// How long browsers may cache a preflight response
+	pub max_age_secs: u64
+}
+
+impl Default for CorsConfig {
+	fn default() -> Self {
+		CorsConfig {
+			allowed_origins: Vec::new(),
+			allowed_methods: vec!["GET".to_owned(), "HEAD".to_owned()],
+			allowed_headers: vec!["Content-Type".to_owned()],
+			max_age_secs: 60 * 60
+		}
+	}
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+	pub file: PathBuf,
+	/// Level for modolumia's own modules, one of `off`, `error`, `warn`, `info`, `debug` or `trace`
+	pub level: String,
+	/// Level for every other crate, like actix
+	pub dependency_level: String,
+	/// Levels for single modules and everything under them, like `"modolumia::database::manager" = "trace"`
+	pub modules: BTreeMap<String, String>,
+	/// `text` or `json`, only affects the log file
+	pub format: String,
+	/// Roll the log file over once it is this many bytes, 0 to never roll over on size
+	pub max_file_size: u64,
+	/// Roll the log file over after this many hours, 0 to never roll over on age
+	pub rotate_hours: u64,
+	/// How many rolled over log files to keep
+	pub keep_files: u32
+}
+
+impl Default for LogConfig {
+	fn default() -> Self {
+		LogConfig {
+			file: PathBuf::from("log/latest.log"),
+			level: if cfg!(debug_assertions) { "debug" } else { "info" }.to_owned(),
+			dependency_level: "info".to_owned(),
+			modules: BTreeMap::new(),
+			format: "text".to_owned(),
+			max_file_size: 10 * 1024 * 1024,
+			rotate_hours: 24,
+			keep_files: 7
+		}
+	}
+}
+
+impl LogConfig {
+	pub fn level_filter(&self) -> LevelFilter {
+		LevelFilter::from_str(&self.level).unwrap_or(LevelFilter::Info)
+	}
+
+	pub fn dependency_level_filter(&self) -> LevelFilter {
+		LevelFilter::from_str(&self.dependency_level).unwrap_or(LevelFilter::Info)
+	}
+
+	pub fn is_json(&self) -> bool {
+		self.format.eq_ignore_ascii_case("json")
+	}
+
+	pub fn rotate_after(&self) -> Option<Duration> {
+		Some(self.rotate_hours).filter(|hours| *hours > 0).map(|hours| Duration::from_secs(hours * 60 * 60))
+	}
+}
+
+impl Config {
+	/// Loads the config file and environment overrides, then validates the result
+	pub fn load() -> Result<Self, Vec<String>> {
+		dotenv().ok();
+		let path = env::var_os("MODOLUMIA_CONFIG").map(PathBuf::from);
+
+		let mut config = match &path {
+			Some(path) => Self::from_file(path)?,
+			None if Path::new(DEFAULT_CONFIG_PATH).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?,
+			None => Config::default()
+		};
+
+		config.apply_env()?;
+		config.validate()?;
+		Ok(config)
+	}
+
+	fn from_file(path: &Path) -> Result<Self, Vec<String>> {
+		let text = fs::read_to_string(path).map_err(|e| vec![format!("Error reading config file {}: {}", path.display(), e)])?;
+		toml::from_str(&text).map_err(|e| vec![format!("Error parsing config file {}: {}", path.display(), e)])
+	}
+
+	fn apply_env(&mut self) -> Result<(), Vec<String>> {
+		let mut errors = Vec::new();
+
+		if let Ok(listen) = env::var("MODOLUMIA_LISTEN") {
+			self.server.listen = listen.split(',').map(|addr| addr.trim().to_owned()).filter(|addr| !addr.is_empty()).collect();
+		}
+		if let Some(workers) = parse_env("MODOLUMIA_WORKERS", &mut errors) {
+			self.server.workers = Some(workers);
+		}
+		override_opt(&mut self.server.admin_token, "ADMIN_TOKEN");
+		override_parsed(&mut self.server.shutdown_timeout_secs, "MODOLUMIA_SHUTDOWN_TIMEOUT", &mut errors);
+
+		override_string(&mut self.database.url, "DATABASE_URL");
+		override_string(&mut self.database.name_prefix, "DATABASE_NAME_PREFIX");
+		override_opt(&mut self.database.namespace, "DATABASE_NAMESPACE");
+		override_string(&mut self.database.auth_mode, "DATABASE_AUTH_MODE");
+		override_opt(&mut self.database.authorization, "DATABASE_AUTHORIZATION");
+		override_opt(&mut self.database.jwt, "DATABASE_JWT");
+		override_path(&mut self.database.ca_bundle, "DATABASE_CA_BUNDLE");
+		override_path(&mut self.database.client_cert, "DATABASE_CLIENT_CERT");
+		override_path(&mut self.database.client_key, "DATABASE_CLIENT_KEY");
+		override_parsed(&mut self.database.tls_insecure, "DATABASE_TLS_INSECURE", &mut errors);
+		override_parsed(&mut self.database.connect_timeout_secs, "DATABASE_CONNECT_TIMEOUT", &mut errors);
+		override_parsed(&mut self.database.read_timeout_secs, "DATABASE_READ_TIMEOUT", &mut errors);
+		override_parsed(&mut self.database.retries, "DATABASE_RETRIES", &mut errors);
+		override_parsed(&mut self.database.cache_documents, "DATABASE_CACHE_DOCUMENTS", &mut errors);
+		override_parsed(&mut self.database.cache_searches, "DATABASE_CACHE_SEARCHES", &mut errors);
+		override_parsed(&mut self.database.cache_ttl_secs, "DATABASE_CACHE_TTL", &mut errors);
+
+		if let Some(dir) = env::var_os("MODOLUMIA_RESOURCES_DIR") {
+			self.static_files.resources_dir = PathBuf::from(dir);
+		}
+		if let Some(dir) = env::var_os("MODOLUMIA_HTML_DIR") {
+			self.static_files.html_dir = PathBuf::from(dir);
+		}
+
+		override_parsed(&mut self.uploads.max_json_size, "MODOLUMIA_MAX_JSON_SIZE", &mut errors);
+		override_parsed(&mut self.uploads.max_file_size, "MODOLUMIA_MAX_FILE_SIZE", &mut errors);
+
+		if let Some(file) = env::var_os("MODOLUMIA_LOG_FILE") {
+			self.logging.file = PathBuf::from(file);
+		}
+		override_string(&mut self.logging.level, "MODOLUMIA_LOG_LEVEL");
+		override_string(&mut self.logging.dependency_level, "MODOLUMIA_LOG_DEPENDENCY_LEVEL");
+		if let Ok(modules) = env::var("MODOLUMIA_LOG_MODULES") {
+			self.logging.modules.clear();
+			for entry in modules.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+				match entry.split_once('=') {
+					Some((module, level)) => { self.logging.modules.insert(module.trim().to_owned(), level.trim().to_owned()); },
+					None => errors.push(format!("MODOLUMIA_LOG_MODULES entry {} must be module=level", entry))
+				}
+			}
+		}
+		override_string(&mut self.logging.format, "MODOLUMIA_LOG_FORMAT");
+		override_parsed(&mut self.logging.max_file_size, "MODOLUMIA_LOG_MAX_SIZE", &mut errors);
+		override_parsed(&mut self.logging.rotate_hours, "MODOLUMIA_LOG_ROTATE_HOURS", &mut errors);
+		override_parsed(&mut self.logging.keep_files, "MODOLUMIA_LOG_KEEP_FILES", &mut errors);
+
+		override_parsed(&mut self.rate_limits.enabled, "MODOLUMIA_RATE_LIMITS", &mut errors);
+		override_parsed(&mut self.rate_limits.trust_proxy, "MODOLUMIA_TRUST_PROXY", &mut errors);
+
+		override_string(&mut self.security.content_security_policy, "MODOLUMIA_CSP");
+		override_parsed(&mut self.security.hsts_max_age_secs, "MODOLUMIA_HSTS_MAX_AGE", &mut errors);
+		override_string(&mut self.security.referrer_policy, "MODOLUMIA_REFERRER_POLICY");
+		override_parsed(&mut self.security.csrf, "MODOLUMIA_CSRF", &mut errors);
+
+		if let Ok(origins) = env::var("MODOLUMIA_CORS_ORIGINS") {
+			self.cors.allowed_origins = origins.split(',').map(|origin| origin.trim().to_owned()).filter(|origin| !origin.is_empty()).collect();
+		}
+		if let Ok(methods) = env::var("MODOLUMIA_CORS_METHODS") {
+			self.cors.allowed_methods = methods.split(',').map(|method| method.trim().to_owned()).filter(|method| !method.is_empty()).collect();
+		}
+		if let Ok(headers) = env::var("MODOLUMIA_CORS_HEADERS") {
+			self.cors.allowed_headers = headers.split(',').map(|header| header.trim().to_owned()).filter(|header| !header.is_empty()).collect();
+		}
+		override_parsed(&mut self.cors.max_age_secs, "MODOLUMIA_CORS_MAX_AGE", &mut errors);
+
+		if errors.is_empty() { Ok(()) } else { Err(errors) }
+	}
+
+	fn validate(&self) -> Result<(), Vec<String>> {
+		let mut errors = Vec::new();
+
+		if self.server.listen.is_empty() {
+			errors.push("server.listen needs at least one address".to_owned());
+		}
+		for addr in &self.server.listen {
+			if addr.parse::<SocketAddr>().is_err() {
+				errors.push(format!("server.listen address {} is not a valid ip:port", addr));
+			}
+		}
+		if self.server.workers == Some(0) {
+			errors.push("server.workers must be at least 1".to_owned());
+		}
+
+		if self.database.url.is_empty() {
+			errors.push("database.url must be set, or DATABASE_URL".to_owned());
+		} else if !self.database.url.starts_with("http://") && !self.database.url.starts_with("https://") {
+			errors.push(format!("database.url {} must be an http or https url", self.database.url));
+		}
+		if !valid_db_name(&self.database.name_prefix) {
+			errors.push(format!("database.name_prefix {} must start with a lowercase letter and only contain lowercase letters, digits and _$()+-/", self.database.name_prefix));
+		}
+		if let Some(namespace) = &self.database.namespace {
+			if !namespace.is_empty() && !namespace.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+				errors.push(format!("database.namespace {} must only contain lowercase letters, digits and _", namespace));
+			}
+		}
+		match self.database.auth_mode.to_lowercase().as_str() {
+			"basic" | "session" => {
+				match &self.database.authorization {
+					Some(auth) if auth.contains(':') => {},
+					Some(_) => errors.push("database.authorization must be in the form username:password".to_owned()),
+					None => errors.push(format!("database.authorization must be set for {} auth, or DATABASE_AUTHORIZATION", self.database.auth_mode))
+				}
+			},
+			"jwt" => if self.database.jwt.is_none() {
+				errors.push("database.jwt must be set for jwt auth, or DATABASE_JWT".to_owned());
+			},
+			other => errors.push(format!("database.auth_mode {} must be basic, session or jwt", other))
+		}
+		if self.database.client_cert.is_some() != self.database.client_key.is_some() {
+			errors.push("database.client_cert and database.client_key must be set together".to_owned());
+		}
+		if self.database.tls_insecure && !cfg!(debug_assertions) {
+			errors.push("database.tls_insecure is only allowed in development builds".to_owned());
+		}
+		if self.database.read_timeout_secs == 0 || self.database.connect_timeout_secs == 0 {
+			errors.push("database timeouts must be at least 1 second".to_owned());
+		}
+		if self.database.cache_documents == 0 || self.database.cache_searches == 0 {
+			errors.push("database cache sizes must be at least 1".to_owned());
+		}
+
+		if self.uploads.max_json_size == 0 || self.uploads.max_file_size == 0 {
+			errors.push("upload limits must be more than 0 bytes".to_owned());
+		}
+
+		if LevelFilter::from_str(&self.logging.level).is_err() {
+			errors.push(format!("logging.level {} must be one of off, error, warn, info, debug or trace", self.logging.level));
+		}
+		if LevelFilter::from_str(&self.logging.dependency_level).is_err() {
+			errors.push(format!("logging.dependency_level {} must be one of off, error, warn, info, debug or trace", self.logging.dependency_level));
+		}
+		for (module, level) in &self.logging.modules {
+			if LevelFilter::from_str(level).is_err() {
+				errors.push(format!("logging.modules level {} for {} must be one of off, error, warn, info, debug or trace", level, module));
+			}
+		}
+		if !self.logging.format.eq_ignore_ascii_case("text") && !self.logging.is_json() {
+			errors.push(format!("logging.format {} must be text or json", self.logging.format));
+		}
+		if self.logging.file.file_name().is_none() {
+			errors.push(format!("logging.file {} must be a file", self.logging.file.display()));
+		}
+
+		for (name, value) in &[("content_security_policy", &self.security.content_security_policy), ("referrer_policy", &self.security.referrer_policy)] {
+			if !value.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+				errors.push(format!("security.{} can only contain printable ASCII", name));
+			}
+		}
+
+		for origin in &self.cors.allowed_origins {
+			if origin != "*" && (!(origin.starts_with("http://") || origin.starts_with("https://")) || origin.ends_with('/')) {
+				errors.push(format!("cors.allowed_origins entry {} must be * or a scheme and host like https://example.com", origin));
+			}
+		}
+		for method in &self.cors.allowed_methods {
+			if actix_web::http::Method::from_bytes(method.as_bytes()).is_err() || method.to_uppercase() != *method {
+				errors.push(format!("cors.allowed_methods entry {} must be an uppercase HTTP method", method));
+			}
+		}
+		for header in &self.cors.allowed_headers {
+			if actix_web::http::HeaderName::from_bytes(header.as_bytes()).is_err() {
+				errors.push(format!("cors.allowed_headers entry {} is not a valid header name", header));
+			}
+		}
+
+		for (group, limit) in &[("search", &self.rate_limits.search), ("uploads", &self.rate_limits.uploads), ("scores", &self.rate_limits.scores), ("login", &self.rate_limits.login)] {
+			if limit.burst == 0 || limit.per_minute == 0 {
+				errors.push(format!("rate_limits.{} burst and per_minute must be at least 1", group));
+			}
+			match limit.key.as_str() {
+				"ip" | "token" => {},
+				"account" => errors.push(format!("rate_limits.{} key account isn't supported yet, there are no accounts", group)),
+				key => errors.push(format!("rate_limits.{} key {} must be ip or token", group, key))
+			}
+		}
+
+		if errors.is_empty() { Ok(()) } else { Err(errors) }
+	}
+}
+
+/// CouchDB database names start with a lowercase letter and only use `a-z0-9_$()+-/`
+fn valid_db_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	match chars.next() {
+		Some(first) if first.is_ascii_lowercase() => chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_$()+-/".contains(c)),
+		_ => false
+	}
+}
+
+fn override_string(field: &mut String, var: &str) {
+	if let Ok(val) = env::var(var) {
+		*field = val;
+	}
+}
+
+fn override_opt(field: &mut Option<String>, var: &str) {
+	if let Ok(val) = env::var(var) {
+		*field = Some(val).filter(|val| !val.is_empty());
+	}
+}
+
+fn override_path(field: &mut Option<PathBuf>, var: &str) {
+	if let Some(val) = env::var_os(var) {
+		*field = Some(PathBuf::from(val));
+	}
+}
+
+fn override_parsed<T: FromStr>(field: &mut T, var: &str, errors: &mut Vec<String>) {
+	if let Some(val) = parse_env(var, errors) {
+		*field = val;
+	}
+}
+
+fn parse_env<T: FromStr>(var: &str, errors: &mut Vec<String>) -> Option<T> {
+	let val = env::var(var).ok()?;
+	match val.parse() {
+		Ok(parsed) => Some(parsed),
+		Err(_) => {
+			errors.push(format!("{} has an invalid value {}", var, val));
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+
+	// The environment is shared by every test thread
+	static ENV: Mutex<()> = Mutex::new(());
+
+	fn with_env<F: FnOnce()>(vars: &[(&str, &str)], test: F) {
+		let _lock = ENV.lock().unwrap_or_else(|e| e.into_inner());
+		for (var, val) in vars {
+			env::set_var(var, val);
+		}
+		let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(test));
+		for (var, _) in vars {
+			env::remove_var(var);
+		}
+		if let Err(e) = res {
+			std::panic::resume_unwind(e);
+		}
+	}
+
+	fn valid() -> Config {
+		let mut config = Config::default();
+		config.database.url = "http://127.0.0.1:5984".to_owned();
+		config.database.authorization = Some("admin:password".to_owned());
+		config
+	}
+
+	#[test]
+	fn env_overrides_the_file() {
+		let mut config: Config = toml::from_str("[database]\ncache_documents = 10\ncache_searches = 20\n[cors]\nallowed_methods = [\"GET\"]").unwrap();
+		with_env(&[("DATABASE_CACHE_DOCUMENTS", "30"), ("MODOLUMIA_CORS_METHODS", "GET, POST,")], || {
+			config.apply_env().unwrap();
+		});
+
+		assert_eq!(config.database.cache_documents, 30);
+		assert_eq!(config.database.cache_searches, 20);
+		assert_eq!(config.cors.allowed_methods, vec!["GET", "POST"]);
+	}
+
+	#[test]
+	fn invalid_env_values_are_errors() {
+		let mut config = Config::default();
+		with_env(&[("DATABASE_CACHE_TTL", "soon"), ("MODOLUMIA_CORS_MAX_AGE", "-1")], || {
+			let errors = config.apply_env().unwrap_err();
+			assert_eq!(errors, vec![
+				"DATABASE_CACHE_TTL has an invalid value soon".to_owned(),
+				"MODOLUMIA_CORS_MAX_AGE has an invalid value -1".to_owned()
+			]);
+		});
+		assert_eq!(config.database.cache_ttl_secs, 60);
+	}
+
+	#[test]
+	fn unknown_file_keys_are_rejected() {
+		assert!(toml::from_str::<Config>("[database]\ncache_size = 10").is_err());
+	}
+
+	#[test]
+	fn validates_a_complete_config() {
+		assert_eq!(valid().validate(), Ok(()));
+		assert!(Config::default().validate().is_err());
+	}
+
+	#[test]
+	fn reports_every_invalid_setting() {
+		let mut config = valid();
+		config.database.read_timeout_secs = 0;
+		config.database.cache_searches = 0;
+		config.cors.allowed_methods = vec!["get".to_owned()];
+		config.rate_limits.search.key = "account".to_owned();
+
+		assert_eq!(config.validate(), Err(vec![
+			"database timeouts must be at least 1 second".to_owned(),
+			"database cache sizes must be at least 1".to_owned(),
+			"cors.allowed_methods entry get must be an uppercase HTTP method".to_owned(),
+			"rate_limits.search key account isn't supported yet, there are no accounts".to_owned()
+		]));
+	}
+
+	#[test]
+	fn database_names() {
+		assert!(valid_db_name("modolumia"));
+		assert!(valid_db_name("mod_2(a)+b-c/d$"));
+		assert!(!valid_db_name(""));
+		assert!(!valid_db_name("2mod"));
+		assert!(!valid_db_name("Modolumia"));
+		assert!(!valid_db_name("mod.lumia"));
+	}
+}
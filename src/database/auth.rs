@@ -0,0 +1,179 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+use crate::util::http_client::{HTTPClient, HTTPResponse, RequestInfo};
+use crate::config::DatabaseConfig;
+use crate::util::metrics;
+
+// CouchDB's default session timeout, used when the cookie doesn't say how long it lasts
+const DEFAULT_SESSION_LENGTH: Duration = Duration::from_secs(600);
+
+/// How `DBManager` authenticates itself to CouchDB
+pub enum AuthStrategy {
+	/// HTTP Basic auth sent with every request
+	Basic {
+		credentials: String
+	},
+	/// Logs in once through `_session` and sends the `AuthSession` cookie,
+	/// logging in again before the cookie expires
+	Session {
+		username: String,
+		password: String
+	},
+	/// A JWT sent as a bearer token, CouchDB needs `jwt_authentication_handler` enabled
+	Jwt {
+		token: String
+	}
+}
+
+impl AuthStrategy {
+	/// Picks the strategy from `auth_mode`, the config has to have been validated first
+	pub fn from_config(config: &DatabaseConfig) -> Self {
+		let credentials = config.authorization.clone().unwrap_or_default();
+
+		match config.auth_mode.to_lowercase().as_str() {
+			"session" => {
+				let mut split = credentials.splitn(2, ':');
+				AuthStrategy::Session {
+					username: split.next().unwrap_or_default().to_owned(),
+					password: split.next().unwrap_or_default().to_owned()
+				}
+			},
+			"jwt" => AuthStrategy::Jwt {
+				token: config.jwt.clone().unwrap_or_default()
+			},
+			_ => AuthStrategy::Basic {
+				credentials
+			}
+		}
+	}
+}
+
+struct SessionCookie {
+	value: String,
+	renew_at: Instant
+}
+
+/// Adds authentication to every request `DBManager` makes, shared between clones
+#[derive(Clone)]
+pub struct DBAuth {
+	strategy: Arc<AuthStrategy>,
+	session: Arc<Mutex<Option<SessionCookie>>>
+}
+
+impl DBAuth {
+	pub fn new(strategy: AuthStrategy) -> Self {
+		DBAuth {
+			strategy: Arc::new(strategy),
+			session: Arc::new(Mutex::new(None))
+		}
+	}
+
+	/// Adds the credentials for the current strategy to a request, logging in first if needed
+	pub async fn authorize(&self, http: &HTTPClient, hostname: &str, info: RequestInfo) -> Result<RequestInfo, String> {
+		match &*self.strategy {
+			AuthStrategy::Basic { credentials } => Ok(info.header("Authorization", &format!("Basic {}", base64::encode(credentials)))),
+			AuthStrategy::Jwt { token } => Ok(info.header("Authorization", &format!("Bearer {}", token))),
+			AuthStrategy::Session { username, password } => {
+				let cookie = match self.current_session() {
+					Some(cookie) => cookie,
+					None => self.login(http, hostname, username, password).await?
+				};
+				Ok(info.header("Cookie", &format!("AuthSession={}", cookie)))
+			}
+		}
+	}
+
+	/// Whether requests are sent with a session cookie, which can expire and make CouchDB answer 401
+	pub fn uses_session(&self) -> bool {
+		matches!(&*self.strategy, AuthStrategy::Session { .. })
+	}
+
+	/// Picks up refreshed session cookies and forgets sessions CouchDB rejected
+	pub fn update(&self, response: &HTTPResponse) {
+		if let AuthStrategy::Session { .. } = &*self.strategy {
+			if response.status_code == 401 {
+				warn!("CouchDB rejected the session cookie, logging in again");
+				self.set_session(None);
+			} else if let Some(cookie) = Self::parse_cookie(response) {
+				self.set_session(Some(cookie));
+			}
+		}
+	}
+
+	/// Replaces the held session, keeping the active sessions metric in step
+	fn set_session(&self, cookie: Option<SessionCookie>) {
+		let mut session = self.session.lock().unwrap();
+		match (session.is_some(), cookie.is_some()) {
+			(false, true) => metrics::COUCHDB_SESSIONS.inc(),
+			(true, false) => metrics::COUCHDB_SESSIONS.dec(),
+			_ => {}
+		}
+		*session = cookie;
+	}
+
+	fn current_session(&self) -> Option<String> {
+		match &*self.session.lock().unwrap() {
+			Some(cookie) if cookie.renew_at > Instant::now() => Some(cookie.value.clone()),
+			_ => None
+		}
+	}
+
+	async fn login(&self, http: &HTTPClient, hostname: &str, username: &str, password: &str) -> Result<String, String> {
+		let data = match serde_json::to_string(&SessionRequest { name: username, password }) {
+			Ok(val) => val,
+			Err(e) => return Err(format!("Error serializing SessionRequest: {}", e))
+		};
+
+		let res = http.request(RequestInfo::post(format!("{}/_session", hostname), data).content_type("application/json".to_owned())).await?;
+		if res.status_code != 200 {
+			return Err(format!("Logging into CouchDB failed with status {}", res.status_code));
+		}
+
+		match Self::parse_cookie(&res) {
+			Some(cookie) => {
+				debug!("Logged into CouchDB as {}", username);
+				let value = cookie.value.clone();
+				self.set_session(Some(cookie));
+				Ok(value)
+			},
+			None => Err("CouchDB did not return a session cookie".to_owned())
+		}
+	}
+
+	/// Finds the `AuthSession` cookie in a response, it is renewed halfway through its lifetime
+	fn parse_cookie(response: &HTTPResponse) -> Option<SessionCookie> {
+		for header in response.headers.get_all("set-cookie") {
+			let header = match header.to_str() {
+				Ok(header) => header,
+				Err(_) => continue
+			};
+
+			let mut parts = header.split(';').map(str::trim);
+			let value = match parts.next().and_then(|cookie| cookie.strip_prefix("AuthSession=")) {
+				Some(value) if !value.is_empty() => value.to_owned(),
+				_ => continue
+			};
+
+			let length = parts
+				.filter_map(|part| part.strip_prefix("Max-Age="))
+				.find_map(|age| age.parse::<u64>().ok())
+				.map(Duration::from_secs)
+				.unwrap_or(DEFAULT_SESSION_LENGTH);
+
+			return Some(SessionCookie {
+				value,
+				renew_at: Instant::now() + length / 2
+			});
+		}
+
+		None
+	}
+}
+
+#[derive(Serialize)]
+struct SessionRequest<'a> {
+	name: &'a str,
+	password: &'a str
+}
@@ -0,0 +1,125 @@
+use serde::{Serialize, Deserialize};
+
+use crate::database::{Document, Id};
+use crate::database::DocumentType;
+use crate::database::validation::ValidationErrors;
+use crate::util::Uuid;
+
+/// A single create, update or delete sent to `_bulk_docs`
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum BulkOperation<T: DocumentType> {
+	Create(Document<T>),
+	Update(Document<T>),
+	Delete(DeletedDocument)
+}
+
+impl<T: DocumentType> BulkOperation<T> {
+	/// Create a new document with a new time ordered id
+	pub fn create(data: T) -> Self {
+		Self::create_with_id(Uuid::time_ordered(), data)
+	}
+
+	/// Create a new document with an id the caller picked, fails with a conflict if it already exists
+	pub fn create_with_id(id: Uuid, data: T) -> Self {
+		BulkOperation::Create(Document {
+			_id: Id::from(id),
+			_rev: None,
+			_attachments: None,
+			fields: data
+		})
+	}
+
+	/// Update an existing document, the document needs its current `_rev`
+	pub fn update(doc: Document<T>) -> Self {
+		BulkOperation::Update(doc)
+	}
+
+	/// Checks the document being created or updated, deletes are always valid
+	pub fn validate(&self) -> Result<(), ValidationErrors> {
+		match self {
+			BulkOperation::Create(doc) | BulkOperation::Update(doc) => doc.fields.validate(),
+			BulkOperation::Delete(_) => Ok(())
+		}
+	}
+
+	/// Delete the document with the given id and revision
+	pub fn delete(id: Id<T>, rev: String) -> Self {
+		BulkOperation::Delete(DeletedDocument {
+			_id: id.into_string(),
+			_rev: rev,
+			_deleted: true
+		})
+	}
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeletedDocument {
+	_id: String,
+	_rev: String,
+	_deleted: bool
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct BulkRequest<T: DocumentType> {
+	pub docs: Vec<BulkOperation<T>>
+}
+
+/// The outcome of a single operation in a `_bulk_docs` request
+#[derive(Deserialize, Serialize, Debug)]
+pub struct BulkResult {
+	pub id: Option<String>,
+	pub ok: Option<bool>,
+	pub rev: Option<String>,
+	pub error: Option<String>,
+	pub reason: Option<String>
+}
+
+impl BulkResult {
+	pub fn is_ok(&self) -> bool {
+		self.ok.unwrap_or(false) && self.error.is_none()
+	}
+
+	/// Converts into the new revision of the document or the reason the operation failed
+	pub fn into_result(self) -> Result<String, String> {
+		if self.is_ok() {
+			match self.rev {
+				Some(rev) => Ok(rev),
+				None => Err("Bulk operation succeeded without a revision".to_owned())
+			}
+		} else {
+			Err(format!("{}: {}", self.error.unwrap_or_else(|| "unknown_error".to_owned()), self.reason.unwrap_or_default()))
+		}
+	}
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct AllDocsRequest<'a, S> {
+	pub keys: &'a [Id<S>]
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct AllDocsResponse<S> where S: DocumentType {
+	#[serde(bound(deserialize = "Document<S>: Deserialize<'de>"))]
+	pub rows: Vec<AllDocsRow<S>>
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct AllDocsRow<S> where S: DocumentType {
+	pub key: String,
+	#[serde(bound(deserialize = "Document<S>: Deserialize<'de>"))]
+	pub doc: Option<Document<S>>,
+	pub error: Option<String>
+}
+
+impl<S: DocumentType> AllDocsRow<S> {
+	/// The document, `None` if it doesn't exist or has been deleted
+	pub fn into_result(self) -> Result<Option<Document<S>>, String> {
+		match (self.doc, self.error) {
+			(Some(doc), _) => Ok(Some(doc)),
+			(None, Some(error)) if error == "not_found" => Ok(None),
+			(None, Some(error)) => Err(format!("Error getting document {}: {}", self.key, error)),
+			(None, None) => Ok(None)
+		}
+	}
+}
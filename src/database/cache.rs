@@ -0,0 +1,173 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use lru::LruCache;
+use serde::Serialize;
+use schemars::JsonSchema;
+use actix_web::web::Bytes;
+
+use crate::database::Databases;
+use crate::util::metrics;
+
+const DEFAULT_DOCUMENT_CAPACITY: usize = 1024;
+const DEFAULT_SEARCH_CAPACITY: usize = 256;
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+	pub document_capacity: usize,
+	pub search_capacity: usize,
+	pub ttl: Duration
+}
+
+impl Default for CacheConfig {
+	fn default() -> Self {
+		CacheConfig {
+			document_capacity: DEFAULT_DOCUMENT_CAPACITY,
+			search_capacity: DEFAULT_SEARCH_CAPACITY,
+			ttl: DEFAULT_TTL
+		}
+	}
+}
+
+#[derive(Serialize, JsonSchema, Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+	pub document_hits: u64,
+	pub document_misses: u64,
+	pub search_hits: u64,
+	pub search_misses: u64
+}
+
+/// A document is cached by its id and the revision that was requested,
+/// `None` being whatever the latest revision was at the time
+type DocumentKey = (Databases, String, Option<String>);
+/// Searches are cached by their serialized `SearchInfo`
+type SearchKey = (Databases, String);
+
+struct CacheEntry {
+	body: Bytes,
+	inserted: Instant
+}
+
+struct CacheStore {
+	documents: LruCache<DocumentKey, CacheEntry>,
+	searches: LruCache<SearchKey, CacheEntry>
+}
+
+/// A bounded LRU cache of raw CouchDB responses shared by every clone of a `DBManager`
+#[derive(Clone)]
+pub struct ReadCache {
+	store: Arc<Mutex<CacheStore>>,
+	ttl: Duration,
+	document_hits: Arc<AtomicU64>,
+	document_misses: Arc<AtomicU64>,
+	search_hits: Arc<AtomicU64>,
+	search_misses: Arc<AtomicU64>
+}
+
+impl ReadCache {
+	pub fn new(config: CacheConfig) -> Self {
+		ReadCache {
+			store: Arc::new(Mutex::new(CacheStore {
+				documents: LruCache::new(config.document_capacity),
+				searches: LruCache::new(config.search_capacity)
+			})),
+			ttl: config.ttl,
+			document_hits: Arc::new(AtomicU64::new(0)),
+			document_misses: Arc::new(AtomicU64::new(0)),
+			search_hits: Arc::new(AtomicU64::new(0)),
+			search_misses: Arc::new(AtomicU64::new(0))
+		}
+	}
+
+	pub fn get_document(&self, database: Databases, id: &str, rev: Option<&str>) -> Option<Bytes> {
+		let key = (database, id.to_owned(), rev.map(str::to_owned));
+		let mut store = self.store.lock().unwrap();
+		let res = Self::get_fresh(&mut store.documents, &key, self.ttl);
+		Self::count("document", res.is_some(), &self.document_hits, &self.document_misses);
+		res
+	}
+
+	pub fn put_document(&self, database: Databases, id: &str, rev: Option<&str>, body: Bytes) {
+		let key = (database, id.to_owned(), rev.map(str::to_owned));
+		self.store.lock().unwrap().documents.put(key, CacheEntry { body, inserted: Instant::now() });
+	}
+
+	pub fn get_search(&self, database: Databases, search: &str) -> Option<Bytes> {
+		let key = (database, search.to_owned());
+		let mut store = self.store.lock().unwrap();
+		let res = Self::get_fresh(&mut store.searches, &key, self.ttl);
+		Self::count("search", res.is_some(), &self.search_hits, &self.search_misses);
+		res
+	}
+
+	pub fn put_search(&self, database: Databases, search: &str, body: Bytes) {
+		let key = (database, search.to_owned());
+		self.store.lock().unwrap().searches.put(key, CacheEntry { body, inserted: Instant::now() });
+	}
+
+	/// Drops every cached revision of a document and every cached search of its database
+	pub fn invalidate_document(&self, database: Databases, id: &str) {
+		let mut store = self.store.lock().unwrap();
+		let stale: Vec<DocumentKey> = store.documents.iter()
+			.filter(|((db, doc_id, _), _)| *db == database && doc_id == id)
+			.map(|(key, _)| key.clone())
+			.collect();
+		for key in stale {
+			store.documents.pop(&key);
+		}
+		Self::invalidate_searches(&mut store, database);
+	}
+
+	/// Drops every cached search of a database, used when a document is created
+	pub fn invalidate_database(&self, database: Databases) {
+		Self::invalidate_searches(&mut self.store.lock().unwrap(), database);
+	}
+
+	pub fn clear(&self) {
+		let mut store = self.store.lock().unwrap();
+		store.documents.clear();
+		store.searches.clear();
+	}
+
+	pub fn stats(&self) -> CacheStats {
+		CacheStats {
+			document_hits: self.document_hits.load(Ordering::Relaxed),
+			document_misses: self.document_misses.load(Ordering::Relaxed),
+			search_hits: self.search_hits.load(Ordering::Relaxed),
+			search_misses: self.search_misses.load(Ordering::Relaxed)
+		}
+	}
+
+	fn invalidate_searches(store: &mut CacheStore, database: Databases) {
+		let stale: Vec<SearchKey> = store.searches.iter()
+			.filter(|((db, _), _)| *db == database)
+			.map(|(key, _)| key.clone())
+			.collect();
+		for key in stale {
+			store.searches.pop(&key);
+		}
+	}
+
+	fn get_fresh<K: std::hash::Hash + Eq>(cache: &mut LruCache<K, CacheEntry>, key: &K, ttl: Duration) -> Option<Bytes> {
+		let expired = match cache.get(key) {
+			Some(entry) if entry.inserted.elapsed() < ttl => return Some(entry.body.clone()),
+			Some(_) => true,
+			None => false
+		};
+
+		if expired {
+			cache.pop(key);
+		}
+		None
+	}
+
+	fn count(cache: &str, hit: bool, hits: &AtomicU64, misses: &AtomicU64) {
+		metrics::CACHE_LOOKUPS.with_label_values(&[cache, if hit { "hit" } else { "miss" }]).inc();
+		if hit {
+			hits.fetch_add(1, Ordering::Relaxed);
+		} else {
+			misses.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+}
@@ -0,0 +1,252 @@
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use actix_web::rt::time::delay_for;
+
+use crate::database::{DBManager, Databases, Document, DocumentType, Id, SaveError};
+use crate::database::search::val_to_str;
+use crate::util::backoff::Backoff;
+
+// How long before the read timeout a longpoll returns, so an idle feed doesn't count as a failed request
+const LONGPOLL_MARGIN: Duration = Duration::from_secs(5);
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A change to a document reported by the `_changes` feed
+#[derive(Debug)]
+pub enum Change<T: DocumentType> {
+	Updated(Document<T>),
+	Deleted {
+		id: Id<T>,
+		rev: String
+	}
+}
+
+impl<T: DocumentType> Change<T> {
+	pub fn id(&self) -> &Id<T> {
+		match self {
+			Change::Updated(doc) => &doc._id,
+			Change::Deleted { id, .. } => id
+		}
+	}
+}
+
+type ChangeCallback<T> = Box<dyn Fn(&Change<T>)>;
+
+/// Where a feed starts reading when it has no stored checkpoint
+pub enum Since {
+	/// Every change since the database was created
+	Beginning,
+	/// Only changes made after the feed started
+	Now
+}
+
+/// A long-running consumer of the `_changes` feed of `T`'s database.<br>
+/// The last processed sequence is stored in a `_local` document named after the feed so it
+/// resumes where it left off after a restart, unless `without_checkpoint` is used.
+pub struct ChangesFeed<T: DocumentType> {
+	manager: DBManager,
+	database: Databases,
+	name: String,
+	start: Since,
+	checkpoint: bool,
+	callbacks: Vec<ChangeCallback<T>>,
+	backoff: Backoff
+}
+
+impl<T: DocumentType> ChangesFeed<T> {
+	pub fn new(manager: DBManager, name: &str) -> Self {
+		ChangesFeed {
+			manager,
+			database: T::DATABASE,
+			name: name.to_owned(),
+			start: Since::Now,
+			checkpoint: true,
+			callbacks: Vec::new(),
+			backoff: Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF)
+		}
+	}
+
+	/// Add a callback that runs for every change in the feed
+	pub fn on_change<F: Fn(&Change<T>) + 'static>(mut self, callback: F) -> Self {
+		self.callbacks.push(Box::new(callback));
+		self
+	}
+
+	pub fn start_from(mut self, start: Since) -> Self {
+		self.start = start;
+		self
+	}
+
+	/// A feed that drops changed documents from `manager`'s read cache, so writes made by other servers
+	/// or straight to CouchDB don't have to wait for the cache TTL
+	pub fn invalidating_cache(manager: DBManager) -> Self {
+		let cache = manager.clone();
+		Self::new(manager, "cache")
+			.without_checkpoint()
+			.on_change(move |change| cache.invalidate_cached(change.id()))
+	}
+
+	/// Always start from `start` and don't save where the feed got to, for feeds every server runs
+	/// that would otherwise overwrite each other's checkpoint
+	pub fn without_checkpoint(mut self) -> Self {
+		self.checkpoint = false;
+		self
+	}
+
+	pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+		self.backoff = Backoff::new(initial, max);
+		self
+	}
+
+	/// Consume the feed forever, reconnecting with backoff whenever a request fails.<br>
+	/// Spawn this on the actix runtime with `actix_web::rt::spawn`
+	pub async fn run(mut self) {
+		let mut checkpoint = loop {
+			match self.load_checkpoint().await {
+				Ok(checkpoint) => break checkpoint,
+				Err(e) => self.wait(&format!("Error loading changes checkpoint for {}: {}", self.name, e)).await
+			}
+		};
+		self.backoff.reset();
+		let timeout = longpoll_timeout(self.manager.read_timeout());
+		info!("Listening for changes on {} from {}", self.database.to_string(), val_to_str(&checkpoint.fields.since));
+
+		loop {
+			let since = val_to_str(&checkpoint.fields.since);
+			let changes = match self.manager.get_changes(self.database, &since, timeout).await {
+				Ok(changes) => changes,
+				Err(e) => {
+					self.wait(&format!("Error reading changes feed {}: {}", self.name, e)).await;
+					continue;
+				}
+			};
+			self.backoff.reset();
+
+			for row in changes.results {
+				match row.into_change::<T>() {
+					Some(Ok(change)) => self.callbacks.iter().for_each(|callback| callback(&change)),
+					Some(Err(e)) => warn!("Skipping change in {}: {}", self.name, e),
+					None => {}
+				}
+			}
+
+			if changes.last_seq == checkpoint.fields.since {
+				continue;
+			}
+
+			checkpoint.fields.since = changes.last_seq;
+			if !self.checkpoint {
+				continue;
+			}
+			match self.manager.put_local_document(self.database, &checkpoint).await {
+				Ok(rev) => checkpoint._rev = Some(rev),
+				Err(SaveError::Conflict) => {
+					// Something else saved the checkpoint, without its _rev every later save would conflict too
+					warn!("Changes checkpoint for {} was changed elsewhere, reloading it", self.name);
+					match self.load_checkpoint().await {
+						Ok(stored) => checkpoint._rev = stored._rev,
+						Err(e) => warn!("Error reloading changes checkpoint for {}: {}", self.name, e)
+					}
+				},
+				Err(e) => warn!("Error saving changes checkpoint for {}: {}", self.name, e)
+			}
+		}
+	}
+
+	async fn load_checkpoint(&self) -> Result<Document<Checkpoint>, String> {
+		let id = format!("changes_{}", self.name);
+		let stored = if self.checkpoint {
+			self.manager.get_local_document::<Checkpoint>(self.database, &id).await?
+		} else {
+			None
+		};
+
+		match stored {
+			Some(checkpoint) => Ok(checkpoint),
+			None => Ok(Document {
+				_id: Id::new(format!("_local/{}", id)),
+				_rev: None,
+				_attachments: None,
+				fields: Checkpoint {
+					since: Value::String(match self.start {
+						Since::Beginning => "0".to_owned(),
+						Since::Now => "now".to_owned()
+					})
+				}
+			})
+		}
+	}
+
+	async fn wait(&mut self, error: &str) {
+		let delay = self.backoff.next_delay();
+		error!("{}, retrying in {:?}", error, delay);
+		delay_for(delay).await;
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Checkpoint {
+	since: Value
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangesResponse {
+	pub results: Vec<ChangeRow>,
+	pub last_seq: Value
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangeRow {
+	pub seq: Value,
+	pub id: String,
+	pub changes: Vec<ChangeRev>,
+	#[serde(default)]
+	pub deleted: bool,
+	pub doc: Option<Value>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangeRev {
+	pub rev: String
+}
+
+impl ChangeRow {
+	/// Converts the row into a typed change, design documents are skipped
+	fn into_change<T: DocumentType>(self) -> Option<Result<Change<T>, String>> {
+		if self.id.starts_with("_design/") {
+			return None;
+		}
+
+		if self.deleted {
+			let rev = self.changes.into_iter().next().map(|change| change.rev).unwrap_or_default();
+			return Some(Ok(Change::Deleted { id: Id::new(self.id), rev }));
+		}
+
+		let id = self.id;
+		match self.doc {
+			Some(doc) => Some(serde_json::from_value::<Document<T>>(doc)
+				.map(Change::Updated)
+				.map_err(|e| format!("Error deserializing document {}: {}", id, e))),
+			None => Some(Err(format!("Change to {} did not include the document", id)))
+		}
+	}
+}
+
+/// The longpoll timeout in milliseconds for a client that gives up after `read_timeout`
+fn longpoll_timeout(read_timeout: Duration) -> u32 {
+	let timeout = read_timeout.saturating_sub(LONGPOLL_MARGIN).max(read_timeout / 2);
+	timeout.as_millis().min(u32::MAX as u128) as u32
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn longpoll_returns_before_the_read_timeout() {
+		assert_eq!(longpoll_timeout(Duration::from_secs(30)), 25000);
+		assert_eq!(longpoll_timeout(Duration::from_secs(6)), 3000);
+		assert_eq!(longpoll_timeout(Duration::from_secs(1)), 500);
+	}
+}
@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_RESET_TIMEOUT: Duration = Duration::from_secs(30);
+
+enum BreakerState {
+	/// Requests go through, counting consecutive failures
+	Closed {
+		failures: u32
+	},
+	/// Requests fail straight away until the reset timeout passes
+	Open {
+		since: Instant
+	},
+	/// A single trial request is let through to see if the database is back
+	HalfOpen {
+		since: Instant
+	}
+}
+
+/// Stops sending requests to CouchDB after repeated failures so callers fail fast
+/// instead of each waiting on timeouts and retries
+#[derive(Clone)]
+pub struct CircuitBreaker {
+	state: Arc<Mutex<BreakerState>>,
+	failure_threshold: u32,
+	reset_timeout: Duration
+}
+
+impl Default for CircuitBreaker {
+	fn default() -> Self {
+		Self::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_RESET_TIMEOUT)
+	}
+}
+
+impl CircuitBreaker {
+	pub fn new(failure_threshold: u32, reset_timeout: Duration) -> Self {
+		CircuitBreaker {
+			state: Arc::new(Mutex::new(BreakerState::Closed { failures: 0 })),
+			failure_threshold,
+			reset_timeout
+		}
+	}
+
+	/// Whether a request may be sent right now
+	pub fn allow(&self) -> bool {
+		let mut state = self.state.lock().unwrap();
+		match *state {
+			BreakerState::Closed { .. } => true,
+			// A trial that never reported back (its future was dropped) shouldn't block forever
+			BreakerState::Open { since } | BreakerState::HalfOpen { since } if since.elapsed() >= self.reset_timeout => {
+				*state = BreakerState::HalfOpen { since: Instant::now() };
+				true
+			},
+			_ => false
+		}
+	}
+
+	pub fn record_success(&self) {
+		let mut state = self.state.lock().unwrap();
+		if let BreakerState::HalfOpen { .. } | BreakerState::Open { .. } = *state {
+			info!("Database is reachable again, closing circuit breaker");
+		}
+		*state = BreakerState::Closed { failures: 0 };
+	}
+
+	pub fn record_failure(&self) {
+		let mut state = self.state.lock().unwrap();
+		match *state {
+			BreakerState::Closed { failures } if failures + 1 < self.failure_threshold => {
+				*state = BreakerState::Closed { failures: failures + 1 };
+			},
+			BreakerState::Open { .. } => {},
+			_ => {
+				warn!("Database requests keep failing, opening circuit breaker for {:?}", self.reset_timeout);
+				*state = BreakerState::Open { since: Instant::now() };
+			}
+		}
+	}
+
+	/// True while requests are being rejected without being sent
+	pub fn is_open(&self) -> bool {
+		match *self.state.lock().unwrap() {
+			BreakerState::Closed { .. } => false,
+			BreakerState::Open { since } | BreakerState::HalfOpen { since } => since.elapsed() < self.reset_timeout
+		}
+	}
+}
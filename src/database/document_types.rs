@@ -1,12 +1,30 @@
 use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use schemars::JsonSchema;
 
-pub trait DocumentType: DeserializeOwned + Serialize {}
+use crate::database::{Databases, Id};
+use crate::database::validation::{Validate, ValidationErrors, required, max_length, at_most, MAX_NAME_LENGTH, MAX_DESCRIPTION_LENGTH, MAX_COLOR};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// `JsonSchema` keeps the schemas served at `/api/schema` in step with the structs
+pub trait DocumentType: DeserializeOwned + Serialize + Validate + JsonSchema {
+	/// The database documents of this type live in
+	const DATABASE: Databases;
+
+
+	/// The name people know the document by, used for slugs in links
+	fn name(&self) -> Option<&str> {
+		None
+	}
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct Palette {
+	#[schemars(length(min = 1, max = "MAX_NAME_LENGTH"))]
 	pub name: String,
+	#[schemars(inner(range(max = "MAX_COLOR")))]
 	pub color: [u32; 6],
+	#[schemars(length(min = 1, max = "MAX_NAME_LENGTH"))]
 	pub author: String,
+	#[schemars(length(max = "MAX_DESCRIPTION_LENGTH"))]
 	pub description: String
 }
 
@@ -21,18 +39,61 @@ impl Palette {
 	}
 }
 
-impl DocumentType for Palette {}
+impl DocumentType for Palette {
+	const DATABASE: Databases = Databases::Palettes;
 
-#[derive(Serialize, Deserialize, Debug)]
+	fn name(&self) -> Option<&str> {
+		Some(&self.name)
+	}
+}
+
+impl Validate for Palette {
+	fn validate(&self) -> Result<(), ValidationErrors> {
+		let mut errors = ValidationErrors::new();
+		check_name(&mut errors, "name", &self.name);
+		check_name(&mut errors, "author", &self.author);
+		errors.check("description", max_length(&self.description, MAX_DESCRIPTION_LENGTH));
+		for (i, color) in self.color.iter().enumerate() {
+			errors.check(&format!("color[{}]", i), at_most(*color, MAX_COLOR));
+		}
+		errors.into_result()
+	}
+}
+
+// Read through `StoredMusicPack` so old packs without a name load, while the schema still requires one
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(from = "StoredMusicPack")]
 pub struct MusicPack {
+	#[schemars(length(min = 1, max = "MAX_NAME_LENGTH"))]
+	name: String,
+	#[schemars(length(min = 1, max = "MAX_NAME_LENGTH"))]
 	author: String,
+	#[schemars(length(max = "MAX_DESCRIPTION_LENGTH"))]
 	description: String,
+	#[schemars(length(min = 1))]
 	file_location: String
 }
 
+#[derive(Deserialize)]
+struct StoredMusicPack {
+	// Music packs made before they had names don't have one
+	#[serde(default)]
+	name: String,
+	author: String,
+	description: String,
+	file_location: String
+}
+
+impl From<StoredMusicPack> for MusicPack {
+	fn from(stored: StoredMusicPack) -> Self {
+		MusicPack::new(stored.name, stored.author, stored.description, stored.file_location)
+	}
+}
+
 impl MusicPack {
-	pub fn new(author: String, description: String, file_location: String) -> Self {
+	pub fn new(name: String, author: String, description: String, file_location: String) -> Self {
 		MusicPack {
+			name,
 			author,
 			description,
 			file_location
@@ -40,13 +101,147 @@ impl MusicPack {
 	}
 }
 
-impl DocumentType for MusicPack {}
+impl DocumentType for MusicPack {
+	const DATABASE: Databases = Databases::MusicPacks;
+
+	fn name(&self) -> Option<&str> {
+		Some(self.name.as_str()).filter(|name| !name.is_empty())
+	}
+}
+
+/// Old music packs without a name can still be read, but saving one needs a name
+impl Validate for MusicPack {
+	fn validate(&self) -> Result<(), ValidationErrors> {
+		let mut errors = ValidationErrors::new();
+		check_name(&mut errors, "name", &self.name);
+		check_name(&mut errors, "author", &self.author);
+		errors.check("description", max_length(&self.description, MAX_DESCRIPTION_LENGTH));
+		errors.check("file_location", required(&self.file_location));
+		errors.into_result()
+	}
+}
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct TexturePack {
+	#[schemars(length(min = 1, max = "MAX_NAME_LENGTH"))]
+	name: String,
+	#[schemars(length(min = 1, max = "MAX_NAME_LENGTH"))]
+	author: String,
+	#[schemars(length(max = "MAX_DESCRIPTION_LENGTH"))]
+	description: String,
+	#[schemars(length(min = 1))]
+	file_location: String
+}
+
+impl TexturePack {
+	pub fn new(name: String, author: String, description: String, file_location: String) -> Self {
+		TexturePack {
+			name,
+			author,
+			description,
+			file_location
+		}
+	}
+}
+
+impl DocumentType for TexturePack {
+	const DATABASE: Databases = Databases::TexturePacks;
+
+	fn name(&self) -> Option<&str> {
+		Some(&self.name)
+	}
+}
+
+impl Validate for TexturePack {
+	fn validate(&self) -> Result<(), ValidationErrors> {
+		let mut errors = ValidationErrors::new();
+		check_name(&mut errors, "name", &self.name);
+		check_name(&mut errors, "author", &self.author);
+		errors.check("description", max_length(&self.description, MAX_DESCRIPTION_LENGTH));
+		errors.check("file_location", required(&self.file_location));
+		errors.into_result()
+	}
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct Account {
+	#[schemars(length(min = 1, max = "MAX_NAME_LENGTH"))]
 	username: String,
-	palettes: Vec<String>,
-	packs: Vec<String>,
-	textures: Vec<String>,
+	palettes: Vec<Id<Palette>>,
+	packs: Vec<Id<MusicPack>>,
+	textures: Vec<Id<TexturePack>>
+}
+
+impl DocumentType for Account {
+	const DATABASE: Databases = Databases::Users;
+
+	fn name(&self) -> Option<&str> {
+		Some(&self.username)
+	}
+}
+
+impl Validate for Account {
+	fn validate(&self) -> Result<(), ValidationErrors> {
+		let mut errors = ValidationErrors::new();
+		check_name(&mut errors, "username", &self.username);
+		errors.into_result()
+	}
+}
+
+fn check_name(errors: &mut ValidationErrors, field: &str, value: &str) {
+	errors.check(field, required(value));
+	errors.check(field, max_length(value, MAX_NAME_LENGTH));
+}
 
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn fields(result: Result<(), ValidationErrors>) -> Vec<String> {
+		result.err().map(|errors| errors.errors.into_iter().map(|error| error.field).collect()).unwrap_or_default()
+	}
+
+	fn valid_palette() -> Palette {
+		Palette::new("Sunset".to_owned(), [0x00_0000, MAX_COLOR, 0xFF_0000, 0x00_FF00, 0x00_00FF, 0x80_8080], "tester".to_owned(), String::new())
+	}
+
+	#[test]
+	fn valid_palettes_pass() {
+		assert!(valid_palette().validate().is_ok());
+	}
+
+	#[test]
+	fn palettes_need_a_name_and_author() {
+		let palette = Palette { name: " ".to_owned(), author: String::new(), ..valid_palette() };
+		assert_eq!(fields(palette.validate()), vec!["name", "author"]);
+	}
+
+	#[test]
+	fn long_names_and_descriptions_are_rejected() {
+		let palette = Palette { name: "a".repeat(MAX_NAME_LENGTH + 1), description: "a".repeat(MAX_DESCRIPTION_LENGTH + 1), ..valid_palette() };
+		assert_eq!(fields(palette.validate()), vec!["name", "description"]);
+
+		let palette = Palette { name: "a".repeat(MAX_NAME_LENGTH), description: "a".repeat(MAX_DESCRIPTION_LENGTH), ..valid_palette() };
+		assert!(palette.validate().is_ok());
+	}
+
+	#[test]
+	fn colors_are_24_bit() {
+		let palette = Palette { color: [0, MAX_COLOR + 1, 0, 0, 0, u32::MAX], ..valid_palette() };
+		assert_eq!(fields(palette.validate()), vec!["color[1]", "color[5]"]);
+	}
+
+	#[test]
+	fn unnamed_music_packs_can_be_read_but_not_saved() {
+		let pack: MusicPack = serde_json::from_str(r#"{"author": "tester", "description": "", "file_location": "song.ogg"}"#).unwrap();
+		assert_eq!(pack.name(), None);
+		assert_eq!(fields(pack.validate()), vec!["name"]);
+	}
+
+	#[test]
+	fn packs_need_a_file() {
+		let pack = TexturePack::new("Blocks".to_owned(), "tester".to_owned(), String::new(), " ".to_owned());
+		assert_eq!(fields(pack.validate()), vec!["file_location"]);
+	}
+}
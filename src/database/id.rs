@@ -0,0 +1,105 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use schemars::JsonSchema;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+
+use crate::util::Uuid;
+
+/// The id of a `T` document, so a palette id can't be used to look something up in the music pack database.<br>
+/// Ids are usually uuids but documents made before that have whatever id CouchDB gave them
+pub struct Id<T> {
+	id: String,
+	// fn() -> T keeps Id Send and Sync whatever T is
+	document: PhantomData<fn() -> T>
+}
+
+impl<T> Id<T> {
+	pub fn new(id: String) -> Self {
+		Id {
+			id,
+			document: PhantomData
+		}
+	}
+
+	pub fn as_str(&self) -> &str {
+		&self.id
+	}
+
+	/// The uuid this id was made from, `None` for ids CouchDB generated
+	pub fn uuid(&self) -> Option<Uuid> {
+		Uuid::from_document_id(&self.id)
+	}
+
+	pub fn into_string(self) -> String {
+		self.id
+	}
+}
+
+impl<T> From<Uuid> for Id<T> {
+	fn from(uuid: Uuid) -> Self {
+		Id::new(uuid.to_string())
+	}
+}
+
+// Derives would require T to implement these too
+
+impl<T> Clone for Id<T> {
+	fn clone(&self) -> Self {
+		Id::new(self.id.clone())
+	}
+}
+
+impl<T> PartialEq for Id<T> {
+	fn eq(&self, other: &Self) -> bool {
+		self.id == other.id
+	}
+}
+
+impl<T> Eq for Id<T> {}
+
+impl<T> Hash for Id<T> {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.id.hash(state);
+	}
+}
+
+impl<T> fmt::Display for Id<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.id)
+	}
+}
+
+impl<T> fmt::Debug for Id<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Id({:?})", self.id)
+	}
+}
+
+impl<T> Serialize for Id<T> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&self.id)
+	}
+}
+
+impl<'de, T> Deserialize<'de> for Id<T> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer).map(Id::new)
+	}
+}
+
+impl<T> JsonSchema for Id<T> {
+	fn is_referenceable() -> bool {
+		false
+	}
+
+	fn schema_name() -> String {
+		"Id".to_owned()
+	}
+
+	fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+		String::json_schema(gen)
+	}
+}
@@ -1,11 +1,32 @@
-use dotenv::dotenv;
-use std::env;
+use std::fmt;
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use actix_web::rt::time::delay_for;
+use chrono::Utc;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use schemars::JsonSchema;
 
 use crate::database::search::{SearchInfo, SearchResult};
-use crate::util::http_client::{HTTPClient, RequestInfo};
+use crate::database::bulk::{BulkOperation, BulkRequest, BulkResult, AllDocsRequest, AllDocsResponse};
+use crate::util::http_client::{HTTPClient, HTTPResponse, RequestInfo, ClientConfig};
+use crate::util::tls::TlsOptions;
+use crate::config::DatabaseConfig;
 use crate::database::DocumentType;
+use crate::database::changes::ChangesResponse;
+use crate::database::cache::{ReadCache, CacheConfig, CacheStats};
+use crate::database::auth::{DBAuth, AuthStrategy};
+use crate::database::circuit::CircuitBreaker;
+use crate::database::names::DatabaseNames;
+use crate::util::metrics;
+use crate::util::Uuid;
+use crate::database::Id;
+use crate::util::backoff::Backoff;
+
+/// Everything but unreserved characters is escaped in document ids, so an id can't add to the url's path or query
+const ID_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
 
 #[derive(Serialize, Deserialize)]
 pub struct Post {
@@ -15,44 +36,192 @@ pub struct Post {
 }
 
 impl Post {
+	#[allow(clippy::inherent_to_string)]
 	pub fn to_string(&self) -> String {
 		format!("<h>{}<\\h><p>{}<\\p>", self.title, self.body)
 	}
 }
 
+/// Why a document couldn't be saved or deleted
+#[derive(Debug)]
+pub enum SaveError {
+	/// The `_rev` given isn't the document's current revision
+	Conflict,
+	Failed(String)
+}
+
+impl fmt::Display for SaveError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SaveError::Conflict => write!(f, "Document update conflict"),
+			SaveError::Failed(e) => write!(f, "{}", e)
+		}
+	}
+}
+
+impl From<String> for SaveError {
+	fn from(e: String) -> Self {
+		SaveError::Failed(e)
+	}
+}
+
 #[derive(Clone)]
 pub struct DBManager {
 	hostname: String,
+	names: DatabaseNames,
 	http: HTTPClient,
-	db_sizes: HashMap<String, u32>
+	auth: DBAuth,
+	cache: ReadCache,
+	breaker: CircuitBreaker,
+	ready: Arc<AtomicBool>,
+	stats: Arc<RwLock<DatabaseStats>>
 }
 
 impl DBManager {
-	pub fn new() -> Self {
-		let mut map = HashMap::new();
-		map.insert("Authorization".to_owned(), Self::encode_credentials());
-
-		let m = DBManager {
-			hostname: Self::establish_connection(),
-			http: HTTPClient::with_headers(map),
-			db_sizes: HashMap::new()
-		};
+	/// Creates a manager without contacting the database, call `initialize` to connect
+	pub fn new(config: &DatabaseConfig) -> Self {
+		Self::with_names(config, DatabaseNames::new(&config.name_prefix, config.namespace.as_deref()))
+	}
+
+	/// Creates a manager that names its databases with `names` instead of the configured prefix and namespace
+	pub fn with_names(config: &DatabaseConfig, names: DatabaseNames) -> Self {
+		DBManager {
+			hostname: config.url.trim_end_matches('/').to_owned(),
+			names,
+			http: Self::create_client(config),
+			auth: DBAuth::new(AuthStrategy::from_config(config)),
+			cache: ReadCache::new(CacheConfig {
+				document_capacity: config.cache_documents,
+				search_capacity: config.cache_searches,
+				ttl: config.cache_ttl()
+			}),
+			breaker: CircuitBreaker::default(),
+			ready: Arc::new(AtomicBool::new(false)),
+			stats: Arc::new(RwLock::new(DatabaseStats::default()))
+		}
+	}
+
+	/// Connects to the database, retrying with backoff until it is reachable, and creates missing required databases and indexes.<br>
+	/// The manager reports itself unavailable until this finishes
+	pub async fn initialize(self) {
+		let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60)).with_jitter();
+
+		loop {
+			let res = match self.ensure_databases().await {
+				Ok(()) => match self.refresh_stats().await {
+					Ok(()) => self.ensure_indexes().await,
+					Err(e) => Err(e)
+				},
+				Err(e) => Err(e)
+			};
+
+			match res {
+				Ok(()) => break,
+				Err(e) => {
+					let delay = backoff.next_delay();
+					error!("Error connecting to the database, retrying in {:?}: {}", delay, e);
+					delay_for(delay).await;
+				}
+			}
+		}
+
+		self.ready.store(true, Ordering::Release);
+		info!("Connected to the database");
+	}
+
+	/// Reloads the database statistics every `interval`, forever
+	pub async fn refresh_stats_every(self, interval: Duration) {
+		loop {
+			delay_for(interval).await;
+			if let Err(e) = self.refresh_stats().await {
+				warn!("Error refreshing database stats: {}", e);
+			}
+		}
+	}
+
+	/// How long a request to CouchDB can wait for its response
+	pub fn read_timeout(&self) -> Duration {
+		self.http.get_config().read_timeout
+	}
+
+	/// The document counts from the last time the stats were refreshed
+	pub fn stats(&self) -> DatabaseStats {
+		self.stats.read().unwrap().clone()
+	}
 
-		m.load_sizes()
+	/// Checks that CouchDB is reachable and every required database and index exists,
+	/// returning what is missing
+	pub async fn check_ready(&self) -> Vec<String> {
+		match self.request(None, "up", RequestInfo::get(format!("{}/_up", self.hostname))).await {
+			Ok(res) if res.status_code == 200 => {},
+			Ok(res) => return vec![format!("CouchDB is up but not ready, status {}", res.status_code)],
+			Err(e) => return vec![format!("CouchDB is unreachable: {}", e)]
+		}
+
+		let mut problems = Vec::new();
+		for database in Databases::REQUIRED.iter() {
+			let indexes = match self.get_index_names(*database).await {
+				Ok(Some(indexes)) => indexes,
+				Ok(None) => {
+					problems.push(format!("Database {} is missing", database.to_string()));
+					continue;
+				},
+				Err(e) => {
+					problems.push(format!("Error checking database {}: {}", database.to_string(), e));
+					continue;
+				}
+			};
+
+			for (name, _) in database.indexes() {
+				if !indexes.iter().any(|index| index == name) {
+					problems.push(format!("Index {} is missing from {}", name, database.to_string()));
+				}
+			}
+		}
+
+		problems
 	}
 
-	pub async fn create_doc<S: DocumentType>(&self, database: Databases, data: S) -> Result<String, String> {
-		let data = match serde_json::to_string(&data) {
+	/// Whether the database has been connected to and isn't currently failing
+	pub fn is_available(&self) -> bool {
+		self.ready.load(Ordering::Acquire) && !self.breaker.is_open()
+	}
+
+	/// Creates a document with a new time ordered id, so ids sort by when documents were created
+	pub async fn create_doc<S: DocumentType>(&self, data: S) -> Result<Id<S>, String> {
+		self.create_doc_with_id(Id::from(Uuid::time_ordered()), data).await
+	}
+
+	/// Creates a document with an id the caller picked.<br>
+	/// Creating the same id twice fails instead of making a duplicate, unless the existing document is exactly `data`,
+	/// so a create whose response was lost can safely be retried
+	pub async fn create_doc_with_id<S: DocumentType>(&self, id: Id<S>, data: S) -> Result<Id<S>, String> {
+		if let Err(errors) = data.validate() {
+			return Err(format!("Invalid document: {}", errors));
+		}
+
+		let data = match serde_json::to_value(&data) {
 			Ok(val) => val,
 			Err(e) => return Err(format!("Error serializing DocumentType: {}", e))
 		};
 
-		let res = self.http.request(RequestInfo::post(format!("{}/{}", self.hostname, database.to_string()), data).content_type("application/json".to_owned())).await?;
+		let res = self.request(Some(S::DATABASE), "create", RequestInfo::put(self.doc_url(S::DATABASE, id.as_str()), data.to_string()).content_type("application/json".to_owned()).idempotent()).await?;
+
+		if res.status_code == 409 {
+			// The client retries the PUT, so the 409 may be from our own first attempt getting through
+			return if self.stored_fields(S::DATABASE, id.as_str()).await? == Some(data) {
+				self.cache.invalidate_database(S::DATABASE);
+				Ok(id)
+			} else {
+				Err(format!("A document with id {} already exists", id))
+			};
+		}
 
-		match serde_json::from_str::<DocumentCreationResponse>(&res.body) {
+		match serde_json::from_slice::<DocumentCreationResponse>(&res.body) {
 			Ok(val) => {
-				if val.ok && (res.status_code == 200 || res.status_code == 201) {
-					Ok(val.id)
+				if val.ok && (res.status_code == 201 || res.status_code == 202) {
+					self.cache.invalidate_database(S::DATABASE);
+					Ok(id)
 				} else {
 					Err("Document creation failed".to_owned())
 				}
@@ -61,72 +230,458 @@ impl DBManager {
 		}
 	}
 
-	pub async fn search_db<S: DocumentType>(&self, database: Databases, search: SearchInfo) -> Result<SearchResult<S>, String> {
+	pub async fn search_db<S: DocumentType>(&self, search: SearchInfo) -> Result<SearchResult<S>, String> {
+		let database = S::DATABASE;
 		let data = match serde_json::to_string(&search) {
 			Ok(val) => val,
 			Err(e) => return Err(format!("Error serializing SearchInfo: {}", e))
 		};
 
-		let res = self.http.request(RequestInfo::post(format!("{}/{}/_find", self.hostname, database.to_string()), data).content_type("application/json".to_owned())).await?;
-		
-		match serde_json::from_str::<SearchResult<S>>(&res.body.clone()) {
+		let body = match self.cache.get_search(database, &data) {
+			Some(body) => body,
+			None => {
+				let res = self.request(Some(database), "find", RequestInfo::post(format!("{}/_find", self.db_url(database)), data.clone()).content_type("application/json".to_owned()).idempotent()).await?;
+				if res.status_code == 200 {
+					self.cache.put_search(database, &data, res.body.clone());
+				}
+				res.body
+			}
+		};
+
+		match serde_json::from_slice::<SearchResult<S>>(&body) {
+			Ok(val) => Ok(val),
+			Err(e) => Err(format!("Error deserializing response: {}", e))
+		}
+	}
+
+	pub async fn get_document<S: DocumentType>(&self, id: &Id<S>) -> Result<Document<S>, String> {
+		match self.get_cached_document(id, None).await? {
+			Some(doc) => Ok(doc),
+			None => Err(format!("Document {} not found", id))
+		}
+	}
+
+	/// Gets a document, returning `None` instead of an error when it doesn't exist
+	pub async fn find_document<S: DocumentType>(&self, id: &Id<S>) -> Result<Option<Document<S>>, String> {
+		self.get_cached_document(id, None).await
+	}
+
+	/// Gets a specific revision of a document
+	pub async fn get_document_rev<S: DocumentType>(&self, id: &Id<S>, rev: &str) -> Result<Document<S>, String> {
+		match self.get_cached_document(id, Some(rev)).await? {
+			Some(doc) => Ok(doc),
+			None => Err(format!("Revision {} of document {} not found", rev, id))
+		}
+	}
+
+	async fn get_cached_document<S: DocumentType>(&self, id: &Id<S>, rev: Option<&str>) -> Result<Option<Document<S>>, String> {
+		let database = S::DATABASE;
+		let body = match self.cache.get_document(database, id.as_str(), rev) {
+			Some(body) => body,
+			None => {
+				let mut req = RequestInfo::get(self.doc_url(database, id.as_str())).query("attachments", "false");
+				if let Some(rev) = rev {
+					req = req.query("rev", rev);
+				}
+				let res = self.request(Some(database), "get", req).await?;
+				match res.status_code {
+					200 => self.cache.put_document(database, id.as_str(), rev, res.body.clone()),
+					404 => return Ok(None),
+					status => return Err(format!("Getting document {} failed with status {}: {}", id, status, String::from_utf8_lossy(&res.body)))
+				}
+				res.body
+			}
+		};
+
+		match serde_json::from_slice::<Document<S>>(&body) {
+			Ok(val) => Ok(Some(val)),
+			Err(e) => Err(format!("Error deserializing response: {}", e))
+		}
+	}
+
+	/// A document's fields as they are in CouchDB right now, without `_id`, `_rev` or attachments
+	async fn stored_fields(&self, database: Databases, id: &str) -> Result<Option<serde_json::Value>, String> {
+		let res = self.request(Some(database), "get", RequestInfo::get(self.doc_url(database, id)).query("attachments", "false")).await?;
+		match res.status_code {
+			200 => {},
+			404 => return Ok(None),
+			status => return Err(format!("Getting document {} failed with status {}: {}", id, status, String::from_utf8_lossy(&res.body)))
+		}
+
+		match serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&res.body) {
+			Ok(mut fields) => {
+				fields.retain(|key, _| !key.starts_with('_'));
+				Ok(Some(serde_json::Value::Object(fields)))
+			},
+			Err(e) => Err(format!("Error deserializing response: {}", e))
+		}
+	}
+
+	/// Drops a document from the read cache, for documents changed outside of this `DBManager`
+	pub fn invalidate_cached<S: DocumentType>(&self, id: &Id<S>) {
+		self.cache.invalidate_document(S::DATABASE, id.as_str());
+	}
+
+	pub fn cache_stats(&self) -> CacheStats {
+		self.cache.stats()
+	}
+
+	/// Saves a new revision of an existing document and returns the new `_rev`
+	pub async fn update_doc<S: DocumentType>(&self, doc: &Document<S>) -> Result<String, SaveError> {
+		if let Err(errors) = doc.fields.validate() {
+			return Err(SaveError::Failed(format!("Invalid document {}: {}", doc._id, errors)));
+		}
+
+		let rev = self.put_document(S::DATABASE, doc, "update").await?;
+		self.cache.invalidate_document(S::DATABASE, doc._id.as_str());
+		Ok(rev)
+	}
+
+	/// Deletes the given revision of a document
+	pub async fn delete_doc<S: DocumentType>(&self, id: &Id<S>, rev: &str) -> Result<String, SaveError> {
+		let database = S::DATABASE;
+		let res = self.request(Some(database), "delete", RequestInfo::delete(self.doc_url(database, id.as_str())).header("If-Match", rev).once()).await?;
+		if res.status_code == 409 {
+			return Err(SaveError::Conflict);
+		}
+
+		match serde_json::from_slice::<DocumentCreationResponse>(&res.body) {
+			Ok(val) => {
+				if val.ok && (res.status_code == 200 || res.status_code == 202) {
+					self.cache.invalidate_document(database, id.as_str());
+					Ok(val.rev)
+				} else {
+					Err(SaveError::Failed("Document deletion failed".to_owned()))
+				}
+			},
+			Err(e) => Err(SaveError::Failed(format!("Error deserializing body: {}", e)))
+		}
+	}
+
+	/// Gets a non-replicated `_local` document, returns `None` if it doesn't exist yet.<br>
+	/// Local documents hold bookkeeping like changes feed checkpoints rather than a `DocumentType`
+	pub async fn get_local_document<S: DeserializeOwned>(&self, database: Databases, id: &str) -> Result<Option<Document<S>>, String> {
+		let res = self.request(Some(database), "get_local", RequestInfo::get(format!("{}/_local/{}", self.db_url(database), id))).await?;
+		if res.status_code == 404 {
+			return Ok(None);
+		}
+
+		match serde_json::from_slice::<Document<S>>(&res.body) {
+			Ok(val) => Ok(Some(val)),
+			Err(e) => Err(format!("Error deserializing response: {}", e))
+		}
+	}
+
+	/// Saves a `_local` document, its `_id` needs the `_local/` prefix
+	pub async fn put_local_document<S: Serialize>(&self, database: Databases, doc: &Document<S>) -> Result<String, SaveError> {
+		self.put_document(database, doc, "put_local").await
+	}
+
+	async fn put_document<S: Serialize>(&self, database: Databases, doc: &Document<S>, operation: &str) -> Result<String, SaveError> {
+		let data = match serde_json::to_string(doc) {
+			Ok(val) => val,
+			Err(e) => return Err(SaveError::Failed(format!("Error serializing Document: {}", e)))
+		};
+
+		// A retry after a lost response would conflict with the revision the first attempt saved
+		let res = self.request(Some(database), operation, RequestInfo::put(self.doc_url(database, doc._id.as_str()), data).content_type("application/json".to_owned()).once()).await?;
+		if res.status_code == 409 {
+			return Err(SaveError::Conflict);
+		}
+
+		match serde_json::from_slice::<DocumentCreationResponse>(&res.body) {
+			Ok(val) => {
+				if val.ok && (res.status_code == 200 || res.status_code == 201) {
+					Ok(val.rev)
+				} else {
+					Err(SaveError::Failed("Document update failed".to_owned()))
+				}
+			},
+			Err(e) => Err(SaveError::Failed(format!("Error deserializing body: {}", e)))
+		}
+	}
+
+	/// Long-polls the `_changes` feed for changes after `since`, waiting at most `timeout` milliseconds
+	pub async fn get_changes(&self, database: Databases, since: &str, timeout: u32) -> Result<ChangesResponse, String> {
+		let res = self.request(Some(database), "changes", RequestInfo::get(format!("{}/_changes?feed=longpoll&include_docs=true", self.db_url(database)))
+			.query("since", since)
+			.query("timeout", &timeout.to_string())).await?;
+
+		if res.status_code != 200 {
+			return Err(format!("Changes request failed with status {}: {}", res.status_code, String::from_utf8_lossy(&res.body)));
+		}
+
+		match serde_json::from_slice::<ChangesResponse>(&res.body) {
 			Ok(val) => Ok(val),
 			Err(e) => Err(format!("Error deserializing response: {}", e))
 		}
 	}
 
-	pub async fn get_document<S: DocumentType>(&self, database: Databases, id: String) -> Result<Document<S>, String> {
-		let res = self.http.request(RequestInfo::get(format!("{}/{}/{}?attachments=false", self.hostname, database.to_string(), id))).await?;
-		match serde_json::from_str::<Document<S>>(&res.body) {
+	/// Creates, updates and deletes many documents in a single request.<br>
+	/// Each operation gets its own result in the same order as `operations`
+	pub async fn bulk_docs<S: DocumentType>(&self, operations: Vec<BulkOperation<S>>) -> Result<Vec<BulkResult>, String> {
+		let database = S::DATABASE;
+		// One invalid document fails the whole request rather than saving the rest
+		for (i, operation) in operations.iter().enumerate() {
+			if let Err(errors) = operation.validate() {
+				return Err(format!("Invalid document at {}: {}", i, errors));
+			}
+		}
+
+		let data = match serde_json::to_string(&BulkRequest { docs: operations }) {
+			Ok(val) => val,
+			Err(e) => return Err(format!("Error serializing BulkRequest: {}", e))
+		};
+
+		let res = self.request(Some(database), "bulk_docs", RequestInfo::post(format!("{}/_bulk_docs", self.db_url(database)), data).content_type("application/json".to_owned())).await?;
+
+		if res.status_code != 201 {
+			return Err(format!("Bulk operation failed with status {}: {}", res.status_code, String::from_utf8_lossy(&res.body)));
+		}
+
+		self.cache.invalidate_database(database);
+		match serde_json::from_slice::<Vec<BulkResult>>(&res.body) {
+			Ok(val) => {
+				for id in val.iter().filter_map(|result| result.id.as_ref()) {
+					self.cache.invalidate_document(database, id);
+				}
+				Ok(val)
+			},
+			Err(e) => Err(format!("Error deserializing response: {}", e))
+		}
+	}
+
+	pub async fn create_docs<S: DocumentType>(&self, data: Vec<S>) -> Result<Vec<BulkResult>, String> {
+		self.bulk_docs(data.into_iter().map(BulkOperation::create).collect()).await
+	}
+
+	/// Gets many documents by id in a single request.<br>
+	/// Results are in the same order as `ids`, missing or deleted documents are `None`
+	pub async fn get_documents<S: DocumentType>(&self, ids: &[Id<S>]) -> Result<Vec<Result<Option<Document<S>>, String>>, String> {
+		let database = S::DATABASE;
+		let data = match serde_json::to_string(&AllDocsRequest { keys: ids }) {
+			Ok(val) => val,
+			Err(e) => return Err(format!("Error serializing AllDocsRequest: {}", e))
+		};
+
+		let res = self.request(Some(database), "all_docs", RequestInfo::post(format!("{}/_all_docs?include_docs=true", self.db_url(database)), data).content_type("application/json".to_owned()).idempotent()).await?;
+		if res.status_code != 200 {
+			return Err(format!("Getting documents failed with status {}: {}", res.status_code, String::from_utf8_lossy(&res.body)));
+		}
+
+		match serde_json::from_slice::<AllDocsResponse<S>>(&res.body) {
+			Ok(val) => Ok(val.rows.into_iter().map(|row| row.into_result()).collect()),
+			Err(e) => Err(format!("Error deserializing response: {}", e))
+		}
+	}
+
+	async fn get_db_info(&self, database: Databases) -> Result<DatabaseInfo, String> {
+		let res = self.request(Some(database), "info", RequestInfo::get(self.db_url(database))).await?;
+		if res.status_code != 200 {
+			return Err(format!("Getting database info failed with status {}", res.status_code));
+		}
+
+		match serde_json::from_slice::<DatabaseInfo>(&res.body) {
 			Ok(val) => Ok(val),
 			Err(e) => Err(format!("Error deserializing response: {}", e))
 		}
 	}
 
-	async fn get_db_size(&self, database:Databases) -> Result<u32, String> {
-		let res = self.http.request(RequestInfo::get(format!("{}/{}", self.hostname, database.to_string()))).await?;
-		match serde_json::from_str::<DatabaseInfo>(&res.body) {
-			// Lol so many unwraps and I'm still pretending to fucking error handle 
-			Ok(val) => Ok(val.doc_count),
+	/// Lists the names of a database's Mango indexes, `None` if the database doesn't exist
+	async fn get_index_names(&self, database: Databases) -> Result<Option<Vec<String>>, String> {
+		let res = self.request(Some(database), "list_indexes", RequestInfo::get(format!("{}/_index", self.db_url(database)))).await?;
+		if res.status_code == 404 {
+			return Ok(None);
+		}
+
+		match serde_json::from_slice::<IndexList>(&res.body) {
+			Ok(val) => Ok(Some(val.indexes.into_iter().map(|index| index.name).collect())),
 			Err(e) => Err(format!("Error deserializing response: {}", e))
 		}
 	}
 
-	fn establish_connection() -> String {
-		dotenv().ok();
-		env::var("DATABASE_URL").expect("DATABASE_URL must be set")
+	/// Creates required databases that don't exist yet, so adding one to `Databases::REQUIRED` doesn't need a manual step.<br>
+	/// Existing databases aren't touched, so this only needs permission to create databases when one is missing
+	async fn ensure_databases(&self) -> Result<(), String> {
+		for database in Databases::REQUIRED.iter() {
+			if self.get_index_names(*database).await?.is_none() {
+				self.create_database(*database).await?;
+			}
+		}
+
+		Ok(())
 	}
 
-	fn encode_credentials() -> String {
-		dotenv().ok();
-		format!("Basic {}", base64::encode(env::var("DATABASE_AUTHORIZATION").expect("DATABASE_AUTHORIZATION must be set")))
+	/// Creates any missing indexes on the required databases, CouchDB ignores ones that already exist
+	async fn ensure_indexes(&self) -> Result<(), String> {
+		for database in Databases::REQUIRED.iter() {
+			for (name, fields) in database.indexes() {
+				let data = serde_json::json!({
+					"index": { "fields": fields },
+					"name": name,
+					"ddoc": name,
+					"type": "json"
+				}).to_string();
+
+				let res = self.request(Some(*database), "create_index", RequestInfo::post(format!("{}/_index", self.db_url(*database)), data).content_type("application/json".to_owned())).await?;
+				if res.status_code != 200 {
+					return Err(format!("Creating index {} on {} failed with status {}", name, database.to_string(), res.status_code));
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// The full name of a database in CouchDB
+	pub fn db_name(&self, database: Databases) -> String {
+		self.names.name(database)
 	}
 
-	#[actix_web::main]
-	async fn load_sizes(mut self) -> Self {
-		let mp_db_size = self.get_db_size(Databases::MusicPacks).await.expect("Error getting music pack db size");
-		let p_db_size = self.get_db_size(Databases::Palettes).await.expect("Error getting palette db size");
+	/// Creates every database and the indexes they need, skipping ones that already exist
+	pub async fn create_databases(&self) -> Result<(), String> {
+		for database in Databases::ALL.iter() {
+			self.create_database(*database).await?;
+		}
 
-		info!("Loaded music packs db with size: {}", mp_db_size);
-		info!("Loaded palette db with size: {}", p_db_size);
+		self.ensure_indexes().await
+	}
+
+	async fn create_database(&self, database: Databases) -> Result<(), String> {
+		let res = self.request(Some(database), "create_database", RequestInfo::put(self.db_url(database), String::new())).await?;
+		match res.status_code {
+			201 | 202 => info!("Created database {}", self.db_name(database)),
+			412 => {},
+			status => return Err(format!("Creating database {} failed with status {}", self.db_name(database), status))
+		}
 
-		self.db_sizes.insert(Databases::MusicPacks.to_string(), mp_db_size);
-		self.db_sizes.insert(Databases::MusicPacks.to_string(), self.get_db_size(Databases::MusicPacks).await.unwrap());
+		Ok(())
+	}
+
+	/// Deletes every database, only meant for cleaning up test databases
+	pub async fn delete_databases(&self) -> Result<(), String> {
+		for database in Databases::ALL.iter() {
+			let res = self.request(Some(*database), "delete_database", RequestInfo::delete(self.db_url(*database))).await?;
+			if res.status_code != 200 && res.status_code != 202 && res.status_code != 404 {
+				return Err(format!("Deleting database {} failed with status {}", self.db_name(*database), res.status_code));
+			}
+		}
+
+		Ok(())
+	}
+
+	fn db_url(&self, database: Databases) -> String {
+		format!("{}/{}", self.hostname, self.db_name(database))
+	}
+
+	fn doc_url(&self, database: Databases, id: &str) -> String {
+		match id.split_once('/') {
+			// The slash after _local and _design is part of CouchDB's path, not the id
+			Some((prefix @ ("_local" | "_design"), name)) => format!("{}/{}/{}", self.db_url(database), prefix, utf8_percent_encode(name, ID_ESCAPES)),
+			_ => format!("{}/{}", self.db_url(database), utf8_percent_encode(id, ID_ESCAPES))
+		}
+	}
+
+	fn create_client(config: &DatabaseConfig) -> HTTPClient {
+		let tls = TlsOptions {
+			ca_bundle: config.ca_bundle.clone(),
+			client_cert: config.client_cert.clone(),
+			client_key: config.client_key.clone(),
+			insecure: config.tls_insecure
+		};
+
+		let tls = if tls.ca_bundle.is_none() && tls.client_cert.is_none() && !tls.insecure {
+			None
+		} else {
+			Some(Arc::new(tls.build().unwrap_or_else(|e| panic!("Invalid database TLS settings: {}", e))))
+		};
 
-		self
+		HTTPClient::new().config(ClientConfig {
+			connect_timeout: config.connect_timeout(),
+			read_timeout: config.read_timeout(),
+			retries: config.retries,
+			tls,
+			..ClientConfig::default()
+		})
+	}
+
+	/// Sends a request through the circuit breaker and auth, recording metrics for `operation` on `database`
+	async fn request(&self, database: Option<Databases>, operation: &str, info: RequestInfo) -> Result<HTTPResponse, String> {
+		let database_label = database.map(|database| database.to_string()).unwrap_or_else(|| "none".to_owned());
+
+		if !self.breaker.allow() {
+			metrics::COUCHDB_ERRORS.with_label_values(&[&database_label, operation, "unavailable"]).inc();
+			return Err("The database is unavailable".to_owned());
+		}
+
+		let timer = metrics::COUCHDB_REQUEST_DURATION.with_label_values(&[&database_label, operation]).start_timer();
+		let retry = if self.auth.uses_session() { info.try_clone() } else { None };
+		let mut res = self.send(info).await;
+		// The session expired before we renewed it, CouchDB didn't run the request so it can be sent again after logging in
+		if let (Ok(first), Some(retry)) = (&res, retry) {
+			if first.status_code == 401 {
+				res = self.send(retry).await;
+			}
+		}
+		timer.observe_duration();
+
+		match res {
+			Ok(res) => {
+				if res.status_code >= 500 {
+					metrics::COUCHDB_ERRORS.with_label_values(&[&database_label, operation, "status"]).inc();
+					self.breaker.record_failure();
+				} else {
+					self.breaker.record_success();
+				}
+				Ok(res)
+			},
+			Err(e) => {
+				metrics::COUCHDB_ERRORS.with_label_values(&[&database_label, operation, "request"]).inc();
+				self.breaker.record_failure();
+				Err(e)
+			}
+		}
+	}
+
+	/// Sends a request with this manager's credentials, logging in first if the session needs it
+	async fn send(&self, info: RequestInfo) -> Result<HTTPResponse, String> {
+		let info = self.auth.authorize(&self.http, &self.hostname, info).await?;
+		let res = self.http.request(info).await?;
+		self.auth.update(&res);
+		Ok(res)
+	}
+
+	async fn refresh_stats(&self) -> Result<(), String> {
+		let mut databases = HashMap::new();
+		for database in Databases::REQUIRED.iter() {
+			let info = self.get_db_info(*database).await.map_err(|e| format!("Error getting {} info: {}", database.to_string(), e))?;
+			debug!("Loaded {} with size: {}", database.to_string(), info.doc_count);
+			databases.insert(database.to_string(), DatabaseCounts {
+				doc_count: info.doc_count,
+				doc_del_count: info.doc_del_count
+			});
+		}
+
+		*self.stats.write().unwrap() = DatabaseStats {
+			databases,
+			refreshed_at: Some(Utc::now().to_rfc3339())
+		};
+
+		Ok(())
 	}
 }
 
 
-#[derive(Deserialize, Debug, Serialize)]
-pub struct Document<T: DocumentType> {
-	pub _id: String,
+#[derive(Deserialize, Debug, Serialize, JsonSchema)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned"))]
+pub struct Document<T> {
+	pub _id: Id<T>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub _rev: Option<String>,
 	#[serde(skip_serializing)]
+	#[schemars(skip)]
 	pub _attachments: Option<HashMap<String, Attachment>>,
 	#[serde(flatten)]
-	#[serde(bound(deserialize = "T: DeserializeOwned"))]
 	pub fields: T
 }
 
@@ -154,8 +709,8 @@ struct DatabaseInfo {
 		db_name: String,
 		purge_seq: String,
 		update_seq: String,
-		sizes: HashMap<String, u32>,
-		props: HashMap<String, String>,
+		sizes: HashMap<String, u64>,
+		props: HashMap<String, serde_json::Value>,
 		doc_del_count: u32,
 		doc_count: u32,
 		disk_format_version: u8,
@@ -164,8 +719,32 @@ struct DatabaseInfo {
 		instance_start_time: String
 }
 
+#[derive(Deserialize, Debug)]
+struct IndexList {
+		indexes: Vec<IndexInfo>
+}
+
+#[derive(Deserialize, Debug)]
+struct IndexInfo {
+		name: String
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct DatabaseStats {
+	pub databases: HashMap<String, DatabaseCounts>,
+	pub refreshed_at: Option<String>
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy)]
+pub struct DatabaseCounts {
+	pub doc_count: u32,
+	pub doc_del_count: u32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Databases {
 	MusicPacks,
+	TexturePacks,
 	Palettes,
 	Highscores,
 	Speedruns,
@@ -173,25 +752,103 @@ pub enum Databases {
 }
 
 impl Databases {
-	#[cfg(debug_assertions)]
-	pub fn to_string(&self) -> String {
-		match &self {
-			Databases::MusicPacks => "modolumia_music_packs_testing".to_owned(),
-			Databases::Highscores => "modolumia_highscores_testing".to_owned(),
-			Databases::Palettes => "modolumia_palettes_testing".to_owned(),
-			Databases::Speedruns => "modolumia_speedruns_testing".to_owned(),
-			Databases::Users => "modolumia_users_testing".to_owned()
+	pub const ALL: [Databases; 6] = [Databases::MusicPacks, Databases::TexturePacks, Databases::Palettes, Databases::Highscores, Databases::Speedruns, Databases::Users];
+
+	/// Databases the server can't run without
+	pub const REQUIRED: [Databases; 3] = [Databases::MusicPacks, Databases::TexturePacks, Databases::Palettes];
+
+	/// The Mango indexes searches on this database rely on, as (name, fields)
+	pub fn indexes(&self) -> &'static [(&'static str, &'static [&'static str])] {
+		match self {
+			Databases::Palettes => &[("palette-name", &["name"]), ("palette-author", &["author"])],
+			Databases::MusicPacks => &[("music-pack-author", &["author"])],
+			Databases::TexturePacks => &[("texture-pack-author", &["author"])],
+			_ => &[]
 		}
 	}
+}
 
-	#[cfg(not(debug_assertions))]
+/// The database's name without the prefix, use `DBManager::db_name` for the full name
+impl Databases {
+	#[allow(clippy::inherent_to_string)]
 	pub fn to_string(&self) -> String {
 		match &self {
-			Databases::MusicPacks => "modolumia_music_packs".to_owned(),
-			Databases::Highscores => "modolumia_highscores".to_owned(),
-			Databases::Palettes => "modolumia_palettes".to_owned(),
-			Databases::Speedruns => "modolumia_speedruns".to_owned(),
-			Databases::Users => "modolumia_users".to_owned()
+			Databases::MusicPacks => "music_packs".to_owned(),
+			Databases::TexturePacks => "texture_packs".to_owned(),
+			Databases::Highscores => "highscores".to_owned(),
+			Databases::Palettes => "palettes".to_owned(),
+			Databases::Speedruns => "speedruns".to_owned(),
+			Databases::Users => "users".to_owned()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::database::{EphemeralDatabases, Palette};
+
+	/// The CouchDB in `TEST_DATABASE_URL`, authorized with `TEST_DATABASE_AUTHORIZATION`
+	fn test_config() -> DatabaseConfig {
+		DatabaseConfig {
+			url: std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL has to be set for database tests"),
+			authorization: std::env::var("TEST_DATABASE_AUTHORIZATION").ok(),
+			retries: 0,
+			..DatabaseConfig::default()
 		}
 	}
-}
\ No newline at end of file
+
+	fn palette(name: &str) -> Palette {
+		Palette::new(name.to_owned(), [0x00_0000, 0xFF_FFFF, 0xFF_0000, 0x00_FF00, 0x00_00FF, 0x80_8080], "tester".to_owned(), String::new())
+	}
+
+	#[test]
+	fn document_ids_stay_in_their_path_segment() {
+		let manager = DBManager::with_names(&DatabaseConfig { url: "http://couch/".to_owned(), ..DatabaseConfig::default() }, DatabaseNames::new("mod", None));
+		let palettes = format!("http://couch/{}", manager.db_name(Databases::Palettes));
+
+		assert_eq!(manager.doc_url(Databases::Palettes, "0a-b_c.d~"), format!("{}/0a-b_c.d~", palettes));
+		assert_eq!(manager.doc_url(Databases::Palettes, "x?open_revs=all#y"), format!("{}/x%3Fopen_revs%3Dall%23y", palettes));
+		assert_eq!(manager.doc_url(Databases::Palettes, "a/../b"), format!("{}/a%2F..%2Fb", palettes));
+		assert_eq!(manager.doc_url(Databases::Palettes, "_local/changes cache"), format!("{}/_local/changes%20cache", palettes));
+	}
+
+	/// Needs a CouchDB, run with `cargo test -- --ignored`
+	#[actix_rt::test]
+	#[ignore]
+	async fn documents_round_trip() {
+		let databases = EphemeralDatabases::create(&test_config()).await.expect("Error creating test databases");
+		let res = round_trip(databases.manager()).await;
+		databases.delete().await.expect("Error deleting test databases");
+		res.unwrap();
+	}
+
+	async fn round_trip(manager: &DBManager) -> Result<(), String> {
+		let id = Id::<Palette>::new("round-trip".to_owned());
+		manager.create_doc_with_id(Id::new(id.as_str().to_owned()), palette("First")).await?;
+		// The same document again is what a retried create looks like, a different one is a real duplicate
+		manager.create_doc_with_id(Id::new(id.as_str().to_owned()), palette("First")).await?;
+		check(manager.create_doc_with_id(Id::new(id.as_str().to_owned()), palette("Other")).await.is_err(), "a different document with the same id is rejected")?;
+
+		let doc = manager.get_document(&id).await?;
+		check(doc.fields.name == "First", "the created document is stored")?;
+		let first_rev = doc._rev.clone().ok_or("The document has no _rev")?;
+
+		let updated = Document { fields: palette("Second"), ..doc };
+		let rev = manager.update_doc(&updated).await.map_err(|e| e.to_string())?;
+		check(manager.get_document(&id).await?.fields.name == "Second", "the update is stored")?;
+
+		let stale = Document { _id: Id::new(id.as_str().to_owned()), _rev: Some(first_rev.clone()), _attachments: None, fields: palette("Stale") };
+		check(matches!(manager.update_doc(&stale).await, Err(SaveError::Conflict)), "updating a stale revision conflicts")?;
+		check(matches!(manager.delete_doc(&id, &first_rev).await, Err(SaveError::Conflict)), "deleting a stale revision conflicts")?;
+
+		manager.delete_doc(&id, &rev).await.map_err(|e| e.to_string())?;
+		check(manager.find_document(&id).await?.is_none(), "the deleted document is gone")
+	}
+
+	/// Fails the round trip without panicking, so the databases still get deleted
+	fn check(ok: bool, what: &str) -> Result<(), String> {
+		if ok { Ok(()) } else { Err(format!("Expected {}", what)) }
+	}
+}
+
@@ -1,13 +1,45 @@
+pub mod id;
+pub use id::Id;
+
 pub mod manager;
 pub use manager::Document;
 pub use manager::DBManager;
 pub use manager::Databases;
+pub use manager::DatabaseStats;
+pub use manager::SaveError;
 
 pub mod search;
 pub use search::SearchTerm;
 pub use search::SearchBuilder;
 
+pub mod changes;
+pub use changes::ChangesFeed;
+pub use changes::Change;
+pub use changes::Since;
+
+pub mod auth;
+pub use auth::AuthStrategy;
+
+pub mod circuit;
+
+pub mod names;
+pub use names::DatabaseNames;
+pub use names::EphemeralDatabases;
+
+pub mod cache;
+pub use cache::CacheConfig;
+
+pub mod bulk;
+pub use bulk::BulkOperation;
+pub use bulk::BulkResult;
+
+pub mod validation;
+pub use validation::Validate;
+pub use validation::ValidationErrors;
+
 mod document_types;
 pub use document_types::MusicPack;
 pub use document_types::Palette;
+pub use document_types::TexturePack;
+pub use document_types::Account;
 pub use document_types::DocumentType;
\ No newline at end of file
@@ -0,0 +1,66 @@
+use rand::Rng;
+
+use crate::config::DatabaseConfig;
+use crate::database::{DBManager, Databases};
+
+/// Turns a `Databases` into its name in CouchDB, `{prefix}_{database}` followed by `_{namespace}` when there is one.<br>
+/// Namespaces let several environments, or several test runs, share one CouchDB
+#[derive(Clone, Debug)]
+pub struct DatabaseNames {
+	prefix: String,
+	namespace: Option<String>
+}
+
+impl DatabaseNames {
+	pub fn new(prefix: &str, namespace: Option<&str>) -> Self {
+		DatabaseNames {
+			prefix: prefix.to_owned(),
+			namespace: namespace.filter(|namespace| !namespace.is_empty()).map(str::to_owned)
+		}
+	}
+
+	/// Names in a random namespace nothing else uses
+	pub fn ephemeral(prefix: &str) -> Self {
+		let id: u64 = rand::thread_rng().gen();
+		DatabaseNames {
+			prefix: prefix.to_owned(),
+			namespace: Some(format!("ephemeral_{:016x}", id))
+		}
+	}
+
+	pub fn name(&self, database: Databases) -> String {
+		match &self.namespace {
+			Some(namespace) => format!("{}_{}_{}", self.prefix, database.to_string(), namespace),
+			None => format!("{}_{}", self.prefix, database.to_string())
+		}
+	}
+}
+
+/// A full set of databases in a random namespace, for tests to `delete` when they're done.<br>
+/// Gives each test its own empty databases so tests can run in parallel against one CouchDB
+pub struct EphemeralDatabases {
+	manager: DBManager
+}
+
+impl EphemeralDatabases {
+	pub async fn create(config: &DatabaseConfig) -> Result<Self, String> {
+		let manager = DBManager::with_names(config, DatabaseNames::ephemeral(&config.name_prefix));
+		if let Err(e) = manager.create_databases().await {
+			// Don't leave behind the databases made before the one that failed
+			if let Err(cleanup) = manager.delete_databases().await {
+				warn!("Error deleting ephemeral databases after a failed create: {}", cleanup);
+			}
+			return Err(e);
+		}
+		Ok(EphemeralDatabases { manager })
+	}
+
+	pub fn manager(&self) -> &DBManager {
+		&self.manager
+	}
+
+	/// Deletes every database, tests should get here even when they fail so nothing is left behind
+	pub async fn delete(self) -> Result<(), String> {
+		self.manager.delete_databases().await
+	}
+}
@@ -30,6 +30,7 @@ pub struct SearchBuilder {
 }
 
 impl SearchBuilder {
+	#[allow(clippy::new_without_default)]
 	pub fn new() -> Self {
 		SearchBuilder {
 			curr_search: SearchInfo::new()
@@ -189,6 +190,7 @@ pub struct SearchTerm {
 }
 
 impl Serialize for SearchTerm {
+	#[allow(clippy::get_first)]
 	fn serialize<S : serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
 		match &self.children {
 			Some(children) => {
@@ -251,7 +253,7 @@ impl SearchTerm {
 
 macro_rules! st_builder {
 	($fnname: ident, $name: expr, $array: expr, $doc: expr) => {
-		#[doc($doc)]
+		#[doc = $doc]
 		pub fn $fnname() -> Self {
 		SearchTerm {
 				children: None,
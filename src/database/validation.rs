@@ -0,0 +1,108 @@
+use std::fmt;
+use serde::Serialize;
+use schemars::JsonSchema;
+
+pub const MAX_NAME_LENGTH: usize = 100;
+pub const MAX_DESCRIPTION_LENGTH: usize = 2000;
+/// Colors are 24 bit RGB
+pub const MAX_COLOR: u32 = 0xFF_FFFF;
+
+/// Field level checks every `DocumentType` passes before `DBManager` saves it
+pub trait Validate {
+	fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// A rule one field broke
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct FieldError {
+	pub field: String,
+	pub message: String
+}
+
+/// Every rule a document broke, not just the first one
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct ValidationErrors {
+	pub errors: Vec<FieldError>
+}
+
+impl ValidationErrors {
+	pub fn new() -> Self {
+		ValidationErrors::default()
+	}
+
+	/// Records `field` as invalid if `rule` failed
+	pub fn check(&mut self, field: &str, rule: Result<(), String>) {
+		if let Err(message) = rule {
+			self.errors.push(FieldError {
+				field: field.to_owned(),
+				message
+			});
+		}
+	}
+
+	pub fn into_result(self) -> Result<(), ValidationErrors> {
+		if self.errors.is_empty() {
+			Ok(())
+		} else {
+			Err(self)
+		}
+	}
+}
+
+impl fmt::Display for ValidationErrors {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let errors: Vec<String> = self.errors.iter().map(|error| format!("{} {}", error.field, error.message)).collect();
+		write!(f, "{}", errors.join(", "))
+	}
+}
+
+// Rules for `ValidationErrors::check`
+
+pub fn required(value: &str) -> Result<(), String> {
+	if value.trim().is_empty() {
+		Err("can't be empty".to_owned())
+	} else {
+		Ok(())
+	}
+}
+
+/// Counts characters rather than bytes
+pub fn max_length(value: &str, max: usize) -> Result<(), String> {
+	if value.chars().count() > max {
+		Err(format!("can't be longer than {} characters", max))
+	} else {
+		Ok(())
+	}
+}
+
+pub fn at_most(value: u32, max: u32) -> Result<(), String> {
+	if value > max {
+		Err(format!("can't be more than {:#X}", max))
+	} else {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn required_rejects_blank_values() {
+		assert!(required("Sunset").is_ok());
+		assert!(required("").is_err());
+		assert!(required(" \t").is_err());
+	}
+
+	#[test]
+	fn max_length_counts_characters() {
+		assert!(max_length("ééé", 3).is_ok());
+		assert!(max_length("éééé", 3).is_err());
+	}
+
+	#[test]
+	fn at_most_allows_the_max() {
+		assert!(at_most(MAX_COLOR, MAX_COLOR).is_ok());
+		assert_eq!(at_most(MAX_COLOR + 1, MAX_COLOR), Err("can't be more than 0xFFFFFF".to_owned()));
+	}
+}
@@ -2,43 +2,116 @@
 extern crate log;
 
 pub mod api;
+pub mod config;
 pub mod database;
+pub mod middleware;
 pub mod util;
 
 use actix_files::{Files};
 
-use actix_web::{App, HttpServer};
+use actix_web::{web, App, HttpServer};
 
 
-use crate::database::{DBManager};
-use crate::util::logging::init_logging;
+use std::time::Duration;
 
+use crate::api::admin::AdminToken;
+use crate::api::resource::Resource;
+use crate::config::Config;
+use crate::database::{ChangesFeed, DBManager, MusicPack, Palette, TexturePack};
+use crate::middleware::{AssignRequestId, CatchPanic, Cors, Csrf, Metrics, RateLimits, RequireCsrf, SecurityHeaders};
+use crate::util::logging::{flush_logs, init_logging, install_panic_hook, LogLevels};
+
+
+const STATS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
 
 fn main() {
-	init_logging();
-	let manager = DBManager::new();
-	run(manager).unwrap();
+	let config = match Config::load() {
+		Ok(config) => config,
+		Err(errors) => {
+			eprintln!("Invalid configuration:");
+			for error in errors {
+				eprintln!("  {}", error);
+			}
+			std::process::exit(1);
+		}
+	};
+
+	let log_levels = init_logging(&config.logging);
+	install_panic_hook();
+	let manager = DBManager::new(&config.database);
+	let res = run(config, manager, log_levels);
+	info!("Server stopped");
+	flush_logs();
+	res.unwrap();
 }
 
 #[actix_web::main]
-async fn run(manager: DBManager) -> std::io::Result<()> {
+async fn run(config: Config, manager: DBManager, log_levels: LogLevels) -> std::io::Result<()> {
+	let background = manager.clone();
+	actix_web::rt::spawn(async move {
+		background.clone().initialize().await;
+		background.refresh_stats_every(STATS_REFRESH_INTERVAL).await;
+	});
+	actix_web::rt::spawn(ChangesFeed::<Palette>::invalidating_cache(manager.clone()).run());
+	actix_web::rt::spawn(ChangesFeed::<MusicPack>::invalidating_cache(manager.clone()).run());
+	actix_web::rt::spawn(ChangesFeed::<TexturePack>::invalidating_cache(manager.clone()).run());
+
+	let admin_token = web::Data::new(AdminToken::new(config.server.admin_token.clone()));
+	let log_levels = web::Data::new(log_levels);
+	let rate_limits = RateLimits::new(&config.rate_limits);
+	let require_csrf = RequireCsrf::new(config.security.csrf);
+	let security = config.security.clone();
+	let cors = config.cors.clone();
+	let static_files = config.static_files.clone();
+	let uploads = config.uploads.clone();
 	let mut server = HttpServer::new(move || App::new()
+			.wrap(CatchPanic)
+			.wrap(Metrics)
+			.wrap(AssignRequestId)
+			.wrap(Csrf::new(security.csrf))
+			.wrap(Cors::new(&cors))
+			.wrap(SecurityHeaders::new(&security))
 			.data(manager.clone())
-			.service(api::palettes::search)
-			.service(Files::new("/resources", "resources"))
-			.service(Files::new("/", "html"))
+			.app_data(admin_token.clone())
+			.app_data(log_levels.clone())
+			.app_data(web::JsonConfig::default().limit(uploads.max_json_size))
+			.app_data(web::PayloadConfig::new(uploads.max_file_size))
+			.service(api::health::healthz)
+			.service(api::health::readyz)
+			.service(api::metrics::prometheus)
+			.service(api::openapi::openapi)
+			.service(api::openapi::docs)
+			.service(api::openapi::docs_style)
+			.service(api::openapi::docs_script)
+			.service(web::scope("/api/schema")
+				.service(api::schema::list)
+				.service(api::schema::get))
+			.configure(|cfg| api::versions::configure(cfg, &manager, &rate_limits, &require_csrf))
+			.service(api::links::short_links::<Palette>(Palette::SHORT_LINK, manager.clone()))
+			.service(api::links::short_links::<MusicPack>(MusicPack::SHORT_LINK, manager.clone()))
+			.service(api::links::short_links::<TexturePack>(TexturePack::SHORT_LINK, manager.clone()))
+			.service(Files::new("/resources", &static_files.resources_dir))
+			.service(Files::new("/", &static_files.html_dir))
 		);
 
+	if let Some(workers) = config.server.workers {
+		server = server.workers(workers);
+	}
+	// SIGTERM stops accepting connections and gives in-flight requests this long to finish
+	server = server.shutdown_timeout(config.server.shutdown_timeout_secs);
+
 	#[cfg(debug_assertions)]
 	{
 		use listenfd::ListenFd;
 		let mut listenfd = ListenFd::from_env();
-		server = if let Some(l) = listenfd.take_tcp_listener(0).unwrap() {
-			server.listen(l)?
-		} else {
-			server.bind("127.0.0.1:3000")?
-		};
+		if let Some(l) = listenfd.take_tcp_listener(0).unwrap() {
+			return server.listen(l)?.run().await;
+		}
+	}
+
+	for addr in &config.server.listen {
+		server = server.bind(addr)?;
 	}
 
 	server.run().await
-}
\ No newline at end of file
+}
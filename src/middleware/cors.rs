@@ -0,0 +1,175 @@
+use std::future::Future;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::{header, HeaderMap, HeaderValue, Method};
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use crate::config::CorsConfig;
+
+/// Headers other sites can read from our responses, on top of the ones browsers always expose
+const EXPOSED_HEADERS: &str = "X-Request-Id, RateLimit-Limit, RateLimit-Remaining, RateLimit-Reset, RateLimit-Policy, Retry-After, Deprecation, Sunset, Link, Location";
+
+/// Answers CORS preflights and adds `Access-Control-*` headers for the origins, methods and headers in the config.<br>
+/// Credentials are never allowed, other sites only get what an anonymous request would
+pub struct Cors {
+	policy: Rc<CorsPolicy>
+}
+
+struct CorsPolicy {
+	any_origin: bool,
+	origins: Vec<String>,
+	methods: Vec<Method>,
+	allow_methods: HeaderValue,
+	allow_headers: HeaderValue,
+	max_age: HeaderValue
+}
+
+impl Cors {
+	pub fn new(config: &CorsConfig) -> Self {
+		// Config validation makes sure these parse
+		Cors {
+			policy: Rc::new(CorsPolicy {
+				any_origin: config.allowed_origins.iter().any(|origin| origin == "*"),
+				origins: config.allowed_origins.clone(),
+				methods: config.allowed_methods.iter().map(|method| Method::from_bytes(method.as_bytes()).unwrap()).collect(),
+				allow_methods: HeaderValue::from_str(&config.allowed_methods.join(", ")).unwrap(),
+				allow_headers: HeaderValue::from_str(&config.allowed_headers.join(", ")).unwrap(),
+				max_age: HeaderValue::from(config.max_age_secs)
+			})
+		}
+	}
+}
+
+impl CorsPolicy {
+	fn enabled(&self) -> bool {
+		!self.origins.is_empty()
+	}
+
+	/// The `Access-Control-Allow-Origin` for a request from `origin`, `None` if it isn't allowed
+	fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+		if self.any_origin {
+			return Some(HeaderValue::from_static("*"));
+		}
+
+		let origin_str = origin.to_str().ok()?;
+		self.origins.iter().find(|allowed| allowed.eq_ignore_ascii_case(origin_str)).map(|_| origin.clone())
+	}
+
+	fn is_preflight(req: &ServiceRequest) -> bool {
+		req.method() == Method::OPTIONS && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+	}
+
+	fn preflight<B>(&self, req: ServiceRequest, allow_origin: HeaderValue) -> ServiceResponse<B> {
+		let method_allowed = req.headers().get(header::ACCESS_CONTROL_REQUEST_METHOD)
+			.and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+			.is_some_and(|method| self.methods.contains(&method));
+
+		let mut response = HttpResponse::NoContent();
+		response.header(header::VARY, "Origin");
+		if method_allowed {
+			response.header(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)
+				.header(header::ACCESS_CONTROL_ALLOW_METHODS, self.allow_methods.clone())
+				.header(header::ACCESS_CONTROL_ALLOW_HEADERS, self.allow_headers.clone())
+				.header(header::ACCESS_CONTROL_MAX_AGE, self.max_age.clone());
+		}
+		req.into_response(response.finish().into_body())
+	}
+
+	fn add_headers(&self, headers: &mut HeaderMap, allow_origin: Option<HeaderValue>) {
+		// Responses differ by origin, caches have to know that even when this origin got nothing
+		headers.append(header::VARY, HeaderValue::from_static("Origin"));
+		if let Some(allow_origin) = allow_origin {
+			headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+			headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_static(EXPOSED_HEADERS));
+		}
+	}
+
+	/// Adds the headers once the inner service answers, to error responses too
+	fn respond<F, B>(self: Rc<Self>, fut: F, allow_origin: Option<HeaderValue>) -> LocalBoxFuture<'static, Result<ServiceResponse<B>, Error>>
+	where
+		F: Future<Output = Result<ServiceResponse<B>, Error>> + 'static
+	{
+		Box::pin(async move {
+			match fut.await {
+				Ok(mut res) => {
+					self.add_headers(res.headers_mut(), allow_origin);
+					Ok(res)
+				},
+				Err(e) => {
+					let mut response = e.as_response_error().error_response();
+					self.add_headers(response.headers_mut(), allow_origin);
+					Err(InternalError::from_response(e, response).into())
+				}
+			}
+		})
+	}
+}
+
+impl<S, B> Transform<S> for Cors
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = CorsMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(CorsMiddleware {
+			service,
+			policy: self.policy.clone()
+		})
+	}
+}
+
+pub struct CorsMiddleware<S> {
+	service: S,
+	policy: Rc<CorsPolicy>
+}
+
+impl<S, B> Service for CorsMiddleware<S>
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		let policy = self.policy.clone();
+		if !policy.enabled() {
+			return Box::pin(self.service.call(req));
+		}
+		let origin = match req.headers().get(header::ORIGIN) {
+			Some(origin) => origin.clone(),
+			// A cache could hand this response to a request with an allowed origin, so it still needs Vary
+			None if !policy.any_origin => return policy.respond(self.service.call(req), None),
+			None => return Box::pin(self.service.call(req))
+		};
+
+		let allow_origin = policy.allow_origin(&origin);
+		if CorsPolicy::is_preflight(&req) {
+			return Box::pin(ok(match allow_origin {
+				Some(allow_origin) => policy.preflight(req, allow_origin),
+				None => req.into_response(HttpResponse::NoContent().header(header::VARY, "Origin").finish().into_body())
+			}));
+		}
+
+		let allow_origin = allow_origin.filter(|_| policy.methods.contains(req.method()));
+		policy.respond(self.service.call(req), allow_origin)
+	}
+}
@@ -0,0 +1,173 @@
+use std::task::{Context, Poll};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::{Error, HttpMessage, HttpResponse};
+use futures::future::{ok, Either, LocalBoxFuture, Ready};
+use rand::Rng;
+
+use crate::util::compare::constant_time_eq;
+
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Gives browsers a `csrf_token` cookie for `RequireCsrf`, pages send it back in `X-CSRF-Token`.<br>
+/// The cookie isn't `HttpOnly` since the page has to read it, other sites can't
+pub struct Csrf {
+	enabled: bool
+}
+
+impl Csrf {
+	pub fn new(enabled: bool) -> Self {
+		Csrf {
+			enabled
+		}
+	}
+}
+
+impl<S, B> Transform<S> for Csrf
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = CsrfMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(CsrfMiddleware {
+			service,
+			enabled: self.enabled
+		})
+	}
+}
+
+pub struct CsrfMiddleware<S> {
+	service: S,
+	enabled: bool
+}
+
+impl<S, B> Service for CsrfMiddleware<S>
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		if !self.enabled || req.cookie(CSRF_COOKIE).is_some() {
+			return Box::pin(self.service.call(req));
+		}
+
+		let secure = req.connection_info().scheme() == "https";
+		let fut = self.service.call(req);
+		Box::pin(async move {
+			let mut res = fut.await?;
+			let cookie = Cookie::build(CSRF_COOKIE, new_token())
+				.path("/")
+				.same_site(SameSite::Strict)
+				.secure(secure)
+				.finish();
+			if let Err(e) = res.response_mut().add_cookie(&cookie) {
+				warn!("Error setting CSRF cookie: {}", e);
+			}
+			Ok(res)
+		})
+	}
+}
+
+fn new_token() -> String {
+	format!("{:032x}", rand::thread_rng().gen::<u128>())
+}
+
+/// Double-submit CSRF check for routes that change things, the `X-CSRF-Token` header has to match the `csrf_token` cookie.<br>
+/// Requests with an `Authorization` header are let through, browsers never add one to a forged request
+#[derive(Clone)]
+pub struct RequireCsrf {
+	enabled: bool
+}
+
+impl RequireCsrf {
+	pub fn new(enabled: bool) -> Self {
+		RequireCsrf {
+			enabled
+		}
+	}
+}
+
+impl<S, B> Transform<S> for RequireCsrf
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = RequireCsrfMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(RequireCsrfMiddleware {
+			service,
+			enabled: self.enabled
+		})
+	}
+}
+
+pub struct RequireCsrfMiddleware<S> {
+	service: S,
+	enabled: bool
+}
+
+impl<S, B> Service for RequireCsrfMiddleware<S>
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		if !self.enabled || is_safe(req.method()) || req.headers().contains_key(header::AUTHORIZATION) || has_valid_token(&req) {
+			return Either::Left(self.service.call(req));
+		}
+
+		let response = HttpResponse::Forbidden().body("Missing or invalid CSRF token");
+		Either::Right(ok(req.into_response(response.into_body())))
+	}
+}
+
+fn is_safe(method: &Method) -> bool {
+	method == Method::GET || method == Method::HEAD || method == Method::OPTIONS
+}
+
+fn has_valid_token(req: &ServiceRequest) -> bool {
+	let cookie = match req.cookie(CSRF_COOKIE) {
+		Some(cookie) => cookie,
+		None => return false
+	};
+
+	req.headers().get(CSRF_HEADER)
+		.is_some_and(|header| !cookie.value().is_empty() && constant_time_eq(header.as_bytes(), cookie.value().as_bytes()))
+}
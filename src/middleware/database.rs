@@ -0,0 +1,86 @@
+use std::task::{Context, Poll};
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use crate::database::DBManager;
+
+/// Answers with a 503 instead of calling the wrapped services while the database is unavailable.<br>
+/// A 500 from a request that lost the database partway through, like one the circuit breaker turned away while half-open, becomes the same 503
+pub struct RequireDatabase {
+	manager: DBManager
+}
+
+impl RequireDatabase {
+	pub fn new(manager: DBManager) -> Self {
+		RequireDatabase {
+			manager
+		}
+	}
+}
+
+impl<S, B> Transform<S> for RequireDatabase
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = RequireDatabaseMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(RequireDatabaseMiddleware {
+			service,
+			manager: self.manager.clone()
+		})
+	}
+}
+
+pub struct RequireDatabaseMiddleware<S> {
+	service: S,
+	manager: DBManager
+}
+
+impl<S, B> Service for RequireDatabaseMiddleware<S>
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		if !self.manager.is_available() {
+			return Box::pin(ok(req.into_response(unavailable().into_body())));
+		}
+
+		let manager = self.manager.clone();
+		let fut = self.service.call(req);
+		Box::pin(async move {
+			let res = fut.await?;
+			if res.status() != StatusCode::INTERNAL_SERVER_ERROR || manager.is_available() {
+				return Ok(res);
+			}
+
+			Ok(res.into_response(unavailable().into_body()))
+		})
+	}
+}
+
+fn unavailable() -> HttpResponse {
+	HttpResponse::ServiceUnavailable()
+		.header("Retry-After", "30")
+		.body("The database is currently unavailable, try again later")
+}
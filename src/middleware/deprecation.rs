@@ -0,0 +1,129 @@
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, HeaderName, HeaderValue};
+use actix_web::Error;
+use chrono::{DateTime, Utc};
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+/// Marks every response from the wrapped services as deprecated with the `Deprecation` (RFC 9745)
+/// and `Sunset` (RFC 8594) headers, and links what replaces each route with `rel="successor-version"`
+pub struct Deprecated {
+	since: DateTime<Utc>,
+	sunset: Option<DateTime<Utc>>,
+	successor: Option<Rc<Successor>>
+}
+
+/// Requests under `prefix` move to the same path under `path`
+struct Successor {
+	prefix: String,
+	path: String
+}
+
+impl Successor {
+	fn link(&self, req: &ServiceRequest) -> Option<HeaderValue> {
+		let rest = req.path().strip_prefix(self.prefix.as_str())?;
+		let query = match req.query_string() {
+			"" => String::new(),
+			query => format!("?{}", query)
+		};
+		HeaderValue::from_str(&format!("<{}{}{}>; rel=\"successor-version\"", self.path, rest, query)).ok()
+	}
+}
+
+impl Deprecated {
+	pub fn new(since: DateTime<Utc>) -> Self {
+		Deprecated {
+			since,
+			sunset: None,
+			successor: None
+		}
+	}
+
+	/// When the routes stop working
+	pub fn sunset(mut self, sunset: DateTime<Utc>) -> Self {
+		self.sunset = Some(sunset);
+		self
+	}
+
+	/// Where clients should move to, the routes are mounted under `prefix` and each links the same route under `path`
+	pub fn successor(mut self, prefix: &str, path: &str) -> Self {
+		self.successor = Some(Rc::new(Successor {
+			prefix: prefix.to_owned(),
+			path: path.to_owned()
+		}));
+		self
+	}
+
+	fn headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+		let mut headers = vec![(HeaderName::from_static("deprecation"), format!("@{}", self.since.timestamp()))];
+		if let Some(sunset) = self.sunset {
+			headers.push((HeaderName::from_static("sunset"), sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string()));
+		}
+
+		headers.into_iter()
+			.map(|(name, value)| (name, HeaderValue::from_str(&value).expect("deprecation headers are ASCII")))
+			.collect()
+	}
+}
+
+impl<S, B> Transform<S> for Deprecated
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = DeprecatedMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(DeprecatedMiddleware {
+			service,
+			headers: Rc::new(self.headers()),
+			successor: self.successor.clone()
+		})
+	}
+}
+
+pub struct DeprecatedMiddleware<S> {
+	service: S,
+	headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+	successor: Option<Rc<Successor>>
+}
+
+impl<S, B> Service for DeprecatedMiddleware<S>
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		let link = self.successor.as_ref().and_then(|successor| successor.link(&req));
+		let fut = self.service.call(req);
+		let headers = self.headers.clone();
+
+		Box::pin(async move {
+			let mut res = fut.await?;
+			for (name, value) in headers.iter() {
+				res.headers_mut().insert(name.clone(), value.clone());
+			}
+			if let Some(link) = link {
+				res.headers_mut().append(header::LINK, link);
+			}
+			Ok(res)
+		})
+	}
+}
@@ -0,0 +1,141 @@
+use std::task::{Context, Poll};
+use std::time::Instant;
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::http::Method;
+use actix_web::Error;
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use crate::util::metrics;
+
+/// Records request counts, latencies and upload sizes for every request
+pub struct Metrics;
+
+impl<S, B> Transform<S> for Metrics
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = MetricsMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(MetricsMiddleware {
+			service
+		})
+	}
+}
+
+pub struct MetricsMiddleware<S> {
+	service: S
+}
+
+impl<S, B> Service for MetricsMiddleware<S>
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		let start = Instant::now();
+		let method = method_label(req.method());
+		let upload_size = req.headers()
+			.get(CONTENT_LENGTH)
+			.and_then(|len| len.to_str().ok())
+			.and_then(|len| len.parse::<u64>().ok())
+			.filter(|len| *len > 0);
+
+		let in_flight = InFlight::start();
+		let fut = self.service.call(req);
+
+		Box::pin(async move {
+			let res = fut.await;
+			drop(in_flight);
+
+			// The route is only known once the request has been routed, and is the pattern
+			// like `/p/{code}` so every path doesn't get its own series
+			let (route, status) = match &res {
+				Ok(res) => (res.request().match_pattern(), res.status().as_u16()),
+				Err(e) => (None, e.as_response_error().status_code().as_u16())
+			};
+			let route = route.unwrap_or_else(|| "unmatched".to_owned());
+			let status = status.to_string();
+
+			metrics::HTTP_REQUESTS.with_label_values(&[method, &route, &status]).inc();
+			metrics::HTTP_REQUEST_DURATION.with_label_values(&[method, &route, &status]).observe(start.elapsed().as_secs_f64());
+			if let Some(size) = upload_size {
+				metrics::UPLOAD_SIZE.with_label_values(&[&route]).observe(size as f64);
+			}
+
+			res
+		})
+	}
+}
+
+/// Counts a request as in flight until dropped, which also happens when the client disconnects and the future is dropped
+struct InFlight;
+
+impl InFlight {
+	fn start() -> Self {
+		metrics::HTTP_REQUESTS_IN_FLIGHT.inc();
+		InFlight
+	}
+}
+
+impl Drop for InFlight {
+	fn drop(&mut self) {
+		metrics::HTTP_REQUESTS_IN_FLIGHT.dec();
+	}
+}
+
+/// The label for a request method, any method clients make up is `other` so they can't add series
+fn method_label(method: &Method) -> &'static str {
+	match *method {
+		Method::GET => "GET",
+		Method::HEAD => "HEAD",
+		Method::POST => "POST",
+		Method::PUT => "PUT",
+		Method::DELETE => "DELETE",
+		Method::PATCH => "PATCH",
+		Method::OPTIONS => "OPTIONS",
+		Method::CONNECT => "CONNECT",
+		Method::TRACE => "TRACE",
+		_ => "other"
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn unknown_methods_share_a_label() {
+		assert_eq!(method_label(&Method::GET), "GET");
+		assert_eq!(method_label(&Method::PATCH), "PATCH");
+		assert_eq!(method_label(&Method::from_bytes(b"FOO1").unwrap()), "other");
+		assert_eq!(method_label(&Method::from_bytes(b"get").unwrap()), "other");
+	}
+
+	#[test]
+	fn dropped_requests_leave_the_in_flight_gauge() {
+		let before = metrics::HTTP_REQUESTS_IN_FLIGHT.get();
+		let in_flight = InFlight::start();
+		assert_eq!(metrics::HTTP_REQUESTS_IN_FLIGHT.get(), before + 1);
+		drop(in_flight);
+		assert_eq!(metrics::HTTP_REQUESTS_IN_FLIGHT.get(), before);
+	}
+}
@@ -0,0 +1,26 @@
+pub mod database;
+pub use database::RequireDatabase;
+
+pub mod metrics;
+pub use metrics::Metrics;
+
+pub mod request_id;
+pub use request_id::{AssignRequestId, RequestId};
+
+pub mod panic;
+pub use panic::CatchPanic;
+
+pub mod deprecation;
+pub use deprecation::Deprecated;
+
+pub mod rate_limit;
+pub use rate_limit::{RateLimit, RateLimits};
+
+pub mod security;
+pub use security::SecurityHeaders;
+
+pub mod cors;
+pub use cors::Cors;
+
+pub mod csrf;
+pub use csrf::{Csrf, RequireCsrf};
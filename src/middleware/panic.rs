@@ -0,0 +1,99 @@
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+use std::task::{Context, Poll};
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::{Error, HttpMessage, HttpResponse, ResponseError};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use serde::Serialize;
+use schemars::JsonSchema;
+
+use crate::middleware::RequestId;
+
+/// Turns a panic while serving a request into a 500 instead of a dropped connection.<br>
+/// The panic itself is logged by the hook from `util::logging::install_panic_hook`
+pub struct CatchPanic;
+
+impl<S, B> Transform<S> for CatchPanic
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = CatchPanicMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(CatchPanicMiddleware {
+			service
+		})
+	}
+}
+
+pub struct CatchPanicMiddleware<S> {
+	service: S
+}
+
+impl<S, B> Service for CatchPanicMiddleware<S>
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+
+		let service = &mut self.service;
+		let fut = match std::panic::catch_unwind(AssertUnwindSafe(|| service.call(req))) {
+			Ok(fut) => fut,
+			Err(_) => return Box::pin(async move { Err(PanicError { request_id }.into()) })
+		};
+
+		Box::pin(async move {
+			match AssertUnwindSafe(fut).catch_unwind().await {
+				Ok(res) => res,
+				Err(_) => Err(PanicError { request_id }.into())
+			}
+		})
+	}
+}
+
+#[derive(Serialize, Debug)]
+struct PanicError {
+	request_id: Option<String>
+}
+
+impl fmt::Display for PanicError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Internal server error")
+	}
+}
+
+impl ResponseError for PanicError {
+	fn error_response(&self) -> HttpResponse {
+		HttpResponse::InternalServerError().json(PanicResponse {
+			error: self.to_string(),
+			request_id: self.request_id.as_deref()
+		})
+	}
+}
+
+/// The body of a 500, `request_id` finds the panic in the logs
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "InternalError")]
+pub struct PanicResponse<'a> {
+	error: String,
+	request_id: Option<&'a str>
+}
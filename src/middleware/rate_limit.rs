@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use crate::api::admin::is_admin_request;
+use crate::config::{LimitConfig, RateLimitConfig};
+use crate::util::metrics;
+
+/// How often buckets that have refilled completely are forgotten
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The rate limit for every route group, shared by all workers
+#[derive(Clone)]
+pub struct RateLimits {
+	pub search: RateLimit,
+	pub uploads: RateLimit,
+	/// For the highscore and speedrun submission routes
+	pub scores: RateLimit,
+	/// For the login route
+	pub login: RateLimit
+}
+
+impl RateLimits {
+	pub fn new(config: &RateLimitConfig) -> Self {
+		RateLimits {
+			search: RateLimit::new("search", &config.search, config),
+			uploads: RateLimit::new("uploads", &config.uploads, config),
+			scores: RateLimit::new("scores", &config.scores, config),
+			login: RateLimit::new("login", &config.login, config)
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LimitKey {
+	Ip,
+	Token
+}
+
+/// Token bucket rate limiting for the wrapped services, each client gets a bucket of `burst` requests
+/// that refills at `per_minute`.<br>
+/// Responses carry `RateLimit-*` headers, and a request with an empty bucket gets a 429 with `Retry-After`
+#[derive(Clone)]
+pub struct RateLimit {
+	limiter: Option<Arc<Limiter>>
+}
+
+impl RateLimit {
+	pub fn new(group: &'static str, limit: &LimitConfig, config: &RateLimitConfig) -> Self {
+		if !config.enabled {
+			return RateLimit { limiter: None };
+		}
+
+		RateLimit {
+			limiter: Some(Arc::new(Limiter {
+				group,
+				burst: limit.burst as f64,
+				per_second: limit.per_minute as f64 / 60.0,
+				key: if limit.key == "token" { LimitKey::Token } else { LimitKey::Ip },
+				trust_proxy: config.trust_proxy,
+				state: Mutex::new(Buckets {
+					buckets: HashMap::new(),
+					last_sweep: Instant::now()
+				})
+			}))
+		}
+	}
+}
+
+struct Limiter {
+	group: &'static str,
+	burst: f64,
+	per_second: f64,
+	key: LimitKey,
+	trust_proxy: bool,
+	state: Mutex<Buckets>
+}
+
+struct Buckets {
+	buckets: HashMap<String, Bucket>,
+	last_sweep: Instant
+}
+
+struct Bucket {
+	tokens: f64,
+	updated: Instant
+}
+
+/// The state of a bucket after taking a request from it
+struct Decision {
+	allowed: bool,
+	remaining: f64
+}
+
+impl Limiter {
+	fn take(&self, key: String, now: Instant) -> Decision {
+		let mut state = self.state.lock().unwrap();
+
+		if now.duration_since(state.last_sweep) >= SWEEP_INTERVAL {
+			let (burst, per_second) = (self.burst, self.per_second);
+			state.buckets.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second < burst);
+			state.last_sweep = now;
+		}
+
+		let bucket = state.buckets.entry(key).or_insert(Bucket {
+			tokens: self.burst,
+			updated: now
+		});
+		bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.per_second).min(self.burst);
+		bucket.updated = now;
+
+		let allowed = bucket.tokens >= 1.0;
+		if allowed {
+			bucket.tokens -= 1.0;
+		}
+		Decision {
+			allowed,
+			remaining: bucket.tokens
+		}
+	}
+
+	fn key(&self, req: &ServiceRequest) -> String {
+		// Only tokens we accepted count, otherwise a new made up token would get a new bucket every request
+		if self.key == LimitKey::Token && is_admin_request(req) {
+			if let Some(token) = req.headers().get("Authorization") {
+				// Hashed so tokens aren't kept around in memory
+				let mut hasher = DefaultHasher::new();
+				token.as_bytes().hash(&mut hasher);
+				return format!("token:{:016x}", hasher.finish());
+			}
+		}
+
+		let ip = if self.trust_proxy {
+			req.connection_info().realip_remote_addr().map(without_port)
+		} else {
+			req.peer_addr().map(|addr| addr.ip().to_string())
+		};
+		format!("ip:{}", ip.as_deref().unwrap_or("unknown"))
+	}
+
+	fn set_headers(&self, headers: &mut HeaderMap, decision: &Decision) {
+		let window = (self.burst / self.per_second).ceil();
+		let reset = ((self.burst - decision.remaining) / self.per_second).ceil();
+
+		let values = [
+			("ratelimit-limit", format!("{}", self.burst)),
+			("ratelimit-remaining", format!("{}", decision.remaining.floor())),
+			("ratelimit-reset", format!("{}", reset)),
+			("ratelimit-policy", format!("{};w={}", self.burst, window))
+		];
+		for (name, value) in values.iter() {
+			if let Ok(value) = HeaderValue::from_str(value) {
+				headers.insert(HeaderName::from_static(name), value);
+			}
+		}
+	}
+
+	/// Seconds until the bucket has a whole request in it again
+	fn retry_after(&self, decision: &Decision) -> u64 {
+		((1.0 - decision.remaining) / self.per_second).ceil().max(1.0) as u64
+	}
+}
+
+/// `realip_remote_addr` falls back to the peer address, which includes the port
+fn without_port(addr: &str) -> String {
+	addr.parse::<SocketAddr>().map(|addr| addr.ip().to_string()).unwrap_or_else(|_| addr.to_owned())
+}
+
+impl<S, B> Transform<S> for RateLimit
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = RateLimitMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(RateLimitMiddleware {
+			service,
+			limiter: self.limiter.clone()
+		})
+	}
+}
+
+pub struct RateLimitMiddleware<S> {
+	service: S,
+	limiter: Option<Arc<Limiter>>
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		let limiter = match &self.limiter {
+			Some(limiter) => limiter.clone(),
+			None => return Box::pin(self.service.call(req))
+		};
+
+		let decision = limiter.take(limiter.key(&req), Instant::now());
+		if !decision.allowed {
+			metrics::RATE_LIMITED.with_label_values(&[limiter.group]).inc();
+			let retry_after = limiter.retry_after(&decision);
+			let mut response = HttpResponse::TooManyRequests()
+				.header("Retry-After", retry_after.to_string())
+				.body(format!("Too many requests, try again in {} seconds", retry_after));
+			limiter.set_headers(response.headers_mut(), &decision);
+			return Box::pin(ok(req.into_response(response.into_body())));
+		}
+
+		let fut = self.service.call(req);
+		Box::pin(async move {
+			let mut res = fut.await?;
+			limiter.set_headers(res.headers_mut(), &decision);
+			Ok(res)
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn limiter(burst: u32, per_minute: u32) -> Limiter {
+		Limiter {
+			group: "test",
+			burst: burst as f64,
+			per_second: per_minute as f64 / 60.0,
+			key: LimitKey::Ip,
+			trust_proxy: false,
+			state: Mutex::new(Buckets {
+				buckets: HashMap::new(),
+				last_sweep: Instant::now()
+			})
+		}
+	}
+
+	fn header(headers: &HeaderMap, name: &str) -> String {
+		headers.get(name).unwrap().to_str().unwrap().to_owned()
+	}
+
+	#[test]
+	fn allows_burst_then_limits() {
+		let limiter = limiter(3, 60);
+		let now = Instant::now();
+		for remaining in [2.0, 1.0, 0.0].iter() {
+			let decision = limiter.take("a".to_owned(), now);
+			assert!(decision.allowed);
+			assert_eq!(decision.remaining, *remaining);
+		}
+		assert!(!limiter.take("a".to_owned(), now).allowed);
+		// Other clients have their own bucket
+		assert!(limiter.take("b".to_owned(), now).allowed);
+	}
+
+	#[test]
+	fn refills_up_to_burst() {
+		let limiter = limiter(2, 60);
+		let now = Instant::now();
+		limiter.take("a".to_owned(), now);
+		limiter.take("a".to_owned(), now);
+		assert!(!limiter.take("a".to_owned(), now).allowed);
+
+		let decision = limiter.take("a".to_owned(), now + Duration::from_secs(1));
+		assert!(decision.allowed);
+		assert_eq!(decision.remaining, 0.0);
+
+		// An hour later the bucket is only full, not 3600 requests deep
+		let decision = limiter.take("a".to_owned(), now + Duration::from_secs(3600));
+		assert_eq!(decision.remaining, 1.0);
+	}
+
+	#[test]
+	fn retry_after_waits_for_a_whole_request() {
+		let limiter = limiter(1, 30);
+		let now = Instant::now();
+		limiter.take("a".to_owned(), now);
+		let decision = limiter.take("a".to_owned(), now);
+		assert!(!decision.allowed);
+		assert_eq!(limiter.retry_after(&decision), 2);
+
+		let decision = limiter.take("a".to_owned(), now + Duration::from_millis(1500));
+		assert!(!decision.allowed);
+		assert_eq!(limiter.retry_after(&decision), 1);
+	}
+
+	#[test]
+	fn headers_describe_the_bucket() {
+		let limiter = limiter(5, 30);
+		let now = Instant::now();
+		limiter.take("a".to_owned(), now);
+		let decision = limiter.take("a".to_owned(), now);
+
+		let mut headers = HeaderMap::new();
+		limiter.set_headers(&mut headers, &decision);
+		assert_eq!(header(&headers, "ratelimit-limit"), "5");
+		assert_eq!(header(&headers, "ratelimit-remaining"), "3");
+		// 2 requests to refill at one every 2 seconds
+		assert_eq!(header(&headers, "ratelimit-reset"), "4");
+		assert_eq!(header(&headers, "ratelimit-policy"), "5;w=10");
+	}
+
+	#[test]
+	fn sweep_forgets_full_buckets() {
+		let limiter = limiter(2, 1);
+		let now = Instant::now();
+		limiter.take("refilled".to_owned(), now);
+		limiter.take("empty".to_owned(), now + Duration::from_secs(59));
+		limiter.take("empty".to_owned(), now + Duration::from_secs(59));
+		assert_eq!(limiter.state.lock().unwrap().buckets.len(), 2);
+
+		limiter.take("other".to_owned(), now + SWEEP_INTERVAL + Duration::from_secs(1));
+		let state = limiter.state.lock().unwrap();
+		assert!(!state.buckets.contains_key("refilled"));
+		assert!(state.buckets.contains_key("empty"));
+		assert!(state.buckets.contains_key("other"));
+	}
+}
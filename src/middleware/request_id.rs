@@ -0,0 +1,89 @@
+use std::task::{Context, Poll};
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use rand::Rng;
+
+use crate::util::logging::{in_request, with_request_id};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The id of the request being served, handlers can read it from the request extensions
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+/// Gives every request an id, reusing a sensible `X-Request-Id` from a proxy in front of us.<br>
+/// The id is sent back in `X-Request-Id` and is on every log line written while serving the request
+pub struct AssignRequestId;
+
+impl<S, B> Transform<S> for AssignRequestId
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = AssignRequestIdMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(AssignRequestIdMiddleware {
+			service
+		})
+	}
+}
+
+pub struct AssignRequestIdMiddleware<S> {
+	service: S
+}
+
+impl<S, B> Service for AssignRequestIdMiddleware<S>
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		let id = req.headers()
+			.get(REQUEST_ID_HEADER)
+			.and_then(|id| id.to_str().ok())
+			.filter(|id| valid_request_id(id))
+			.map(str::to_owned)
+			.unwrap_or_else(new_request_id);
+
+		req.extensions_mut().insert(RequestId(id.clone()));
+		// Calling the service can already log, so it needs the id set too
+		let service = &mut self.service;
+		let fut = with_request_id(id.clone(), in_request(&id, || service.call(req)));
+
+		Box::pin(async move {
+			let mut res = fut.await?;
+			if let Ok(value) = HeaderValue::from_str(&id) {
+				res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+			}
+			Ok(res)
+		})
+	}
+}
+
+/// Ids from outside end up in our logs, so only short plain ones are kept
+fn valid_request_id(id: &str) -> bool {
+	!id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn new_request_id() -> String {
+	format!("{:016x}", rand::thread_rng().gen::<u64>())
+}
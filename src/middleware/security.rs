@@ -0,0 +1,106 @@
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::{header, HeaderMap, HeaderName, HeaderValue};
+use actix_web::Error;
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use crate::config::SecurityConfig;
+
+/// Adds `Content-Security-Policy`, `Strict-Transport-Security`, `X-Content-Type-Options` and `Referrer-Policy`
+/// to every response, including errors.<br>
+/// Headers a handler already set are left alone, so a page can send a stricter or looser policy of its own
+pub struct SecurityHeaders {
+	headers: Rc<Vec<(HeaderName, HeaderValue)>>
+}
+
+impl SecurityHeaders {
+	pub fn new(config: &SecurityConfig) -> Self {
+		let mut headers = vec![(header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_owned())];
+		if !config.content_security_policy.is_empty() {
+			headers.push((header::CONTENT_SECURITY_POLICY, config.content_security_policy.clone()));
+		}
+		if config.hsts_max_age_secs > 0 {
+			headers.push((header::STRICT_TRANSPORT_SECURITY, format!("max-age={}; includeSubDomains", config.hsts_max_age_secs)));
+		}
+		if !config.referrer_policy.is_empty() {
+			headers.push((header::REFERRER_POLICY, config.referrer_policy.clone()));
+		}
+
+		SecurityHeaders {
+			// Config validation makes sure these are valid header values
+			headers: Rc::new(headers.into_iter().map(|(name, value)| (name, HeaderValue::from_str(&value).unwrap())).collect())
+		}
+	}
+}
+
+impl<S, B> Transform<S> for SecurityHeaders
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = SecurityHeadersMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(SecurityHeadersMiddleware {
+			service,
+			headers: self.headers.clone()
+		})
+	}
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+	service: S,
+	headers: Rc<Vec<(HeaderName, HeaderValue)>>
+}
+
+impl<S, B> Service for SecurityHeadersMiddleware<S>
+where
+	S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		let fut = self.service.call(req);
+		let headers = self.headers.clone();
+
+		Box::pin(async move {
+			match fut.await {
+				Ok(mut res) => {
+					add_headers(res.headers_mut(), &headers);
+					Ok(res)
+				},
+				Err(e) => {
+					// Errors are rendered here so they get the headers too
+					let mut response = e.as_response_error().error_response();
+					add_headers(response.headers_mut(), &headers);
+					Err(InternalError::from_response(e, response).into())
+				}
+			}
+		})
+	}
+}
+
+fn add_headers(map: &mut HeaderMap, headers: &[(HeaderName, HeaderValue)]) {
+	for (name, value) in headers {
+		if !map.contains_key(name) {
+			map.insert(name.clone(), value.clone());
+		}
+	}
+}
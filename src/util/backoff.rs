@@ -0,0 +1,52 @@
+use std::time::Duration;
+use rand::Rng;
+
+/// Exponential backoff between retries of a failing operation
+#[derive(Clone, Debug)]
+pub struct Backoff {
+	initial: Duration,
+	max: Duration,
+	jitter: bool,
+	attempt: u32
+}
+
+impl Backoff {
+	pub fn new(initial: Duration, max: Duration) -> Self {
+		Backoff {
+			initial,
+			max,
+			jitter: false,
+			attempt: 0
+		}
+	}
+
+	/// Randomize each delay between half and all of its exponential value
+	/// so many clients retrying at once don't all hit the server together
+	pub fn with_jitter(mut self) -> Self {
+		self.jitter = true;
+		self
+	}
+
+	/// Returns how long to wait before the next attempt and counts the attempt
+	pub fn next_delay(&mut self) -> Duration {
+		let delay = self.initial.checked_mul(2u32.saturating_pow(self.attempt)).unwrap_or(self.max);
+		self.attempt = self.attempt.saturating_add(1);
+		let delay = delay.min(self.max);
+
+		if self.jitter {
+			let half = delay / 2;
+			half + half.mul_f64(rand::thread_rng().gen::<f64>())
+		} else {
+			delay
+		}
+	}
+
+	/// Starts over from the initial delay, call after a successful attempt
+	pub fn reset(&mut self) {
+		self.attempt = 0;
+	}
+
+	pub fn attempts(&self) -> u32 {
+		self.attempt
+	}
+}
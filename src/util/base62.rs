@@ -0,0 +1,63 @@
+const ALPHABET: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Enough base62 digits to hold any u128
+pub const U128_LENGTH: usize = 22;
+
+/// Encodes a u128 as exactly `U128_LENGTH` base62 digits, padded with leading zeros
+pub fn encode_u128(mut value: u128) -> String {
+	let mut digits = [b'0'; U128_LENGTH];
+	for digit in digits.iter_mut().rev() {
+		*digit = ALPHABET[(value % 62) as usize];
+		value /= 62;
+	}
+	String::from_utf8_lossy(&digits).into_owned()
+}
+
+/// Decodes base62 digits back into a u128, failing on anything that isn't a digit or doesn't fit
+pub fn decode_u128(code: &str) -> Option<u128> {
+	if code.is_empty() || code.len() > U128_LENGTH {
+		return None;
+	}
+
+	code.bytes().try_fold(0u128, |value, byte| {
+		let digit = ALPHABET.iter().position(|c| *c == byte)? as u128;
+		value.checked_mul(62)?.checked_add(digit)
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trips() {
+		for value in &[0, 1, 61, 62, 0x7919a79bb2564782bf3613990ca65bb7, u128::MAX] {
+			let code = encode_u128(*value);
+			assert_eq!(code.len(), U128_LENGTH);
+			assert_eq!(decode_u128(&code), Some(*value));
+		}
+	}
+
+	#[test]
+	fn encodes_with_padding() {
+		assert_eq!(encode_u128(0), "0000000000000000000000");
+		assert_eq!(encode_u128(62), "0000000000000000000010");
+		assert_eq!(encode_u128(u128::MAX), "7n42DGM5Tflk9n8mt7Fhc7");
+	}
+
+	#[test]
+	fn decodes_short_codes() {
+		assert_eq!(decode_u128("z"), Some(61));
+		assert_eq!(decode_u128("10"), Some(62));
+	}
+
+	#[test]
+	fn rejects_invalid_codes() {
+		assert_eq!(decode_u128(""), None);
+		assert_eq!(decode_u128("abc-def"), None);
+		assert_eq!(decode_u128("é"), None);
+		// Too many digits, and 22 digits that don't fit in a u128
+		assert_eq!(decode_u128(&"1".repeat(U128_LENGTH + 1)), None);
+		assert_eq!(decode_u128(&"z".repeat(U128_LENGTH)), None);
+	}
+}
@@ -0,0 +1,4 @@
+/// Compares every byte so the time taken doesn't leak how much of a secret was right
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
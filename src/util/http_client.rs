@@ -1,68 +1,159 @@
-use actix_web::client::{Client, ClientResponse};
-use actix_web::http::HeaderMap;
+use actix_web::client::{Client, ClientResponse, Connector, SendRequestError};
+use actix_web::http::{HeaderMap, Method};
+use actix_web::web::Bytes;
+use actix_http::encoding::Decoder;
+use actix_http::error::PayloadError;
+use actix_http::Payload;
+use serde::de::DeserializeOwned;
+use tokio::stream::Stream;
+use actix_web::rt::time::delay_for;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::util::backoff::Backoff;
+use crate::util::metrics;
+
+// Every HTTPClient gets its own pool of connections on each worker thread
+static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+	// The Weak is the HTTPClient's `alive`, pools are dropped once every clone of their client is gone
+	static CLIENTS: RefCell<HashMap<usize, (Weak<()>, Client)>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Clone)]
+pub struct ClientConfig {
+	/// How long to wait for a connection to be established
+	pub connect_timeout: Duration,
+	/// How long to wait for a response once the request is sent
+	pub read_timeout: Duration,
+	/// How many times to retry a failed idempotent request
+	pub retries: u32,
+	pub initial_backoff: Duration,
+	pub max_backoff: Duration,
+	/// The largest response body that will be read
+	pub max_response_size: usize,
+	/// TLS settings for https urls, the public web roots are trusted when this is `None`
+	pub tls: Option<Arc<rustls::ClientConfig>>
+}
+
+impl Default for ClientConfig {
+	fn default() -> Self {
+		ClientConfig {
+			connect_timeout: Duration::from_secs(5),
+			read_timeout: Duration::from_secs(30),
+			retries: 3,
+			initial_backoff: Duration::from_millis(100),
+			max_backoff: Duration::from_secs(5),
+			max_response_size: 32 * 1024 * 1024,
+			tls: None
+		}
+	}
+}
 
 #[derive(Clone)]
 pub struct HTTPClient {
-	headers: Option<HashMap<String, String>>
+	id: usize,
+	alive: Arc<()>,
+	headers: Option<HashMap<String, String>>,
+	config: Arc<ClientConfig>
 }
 
 impl HTTPClient {
 
+	#[allow(clippy::new_without_default)]
 	pub fn new() -> Self {
 		HTTPClient {
-			headers: None
+			id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+			alive: Arc::new(()),
+			headers: None,
+			config: Arc::new(ClientConfig::default())
 		}
 	}
 
 	pub fn with_headers(headers: HashMap<String, String>) -> Self {
 		HTTPClient {
-			headers: Some(headers)
+			headers: Some(headers),
+			..Self::new()
 		}
 	}
 
+	pub fn config(mut self, config: ClientConfig) -> Self {
+		self.config = Arc::new(config);
+		self
+	}
+
+	pub fn get_config(&self) -> &ClientConfig {
+		&self.config
+	}
+
+	/// Sends the request and reads the whole response body,
+	/// retrying idempotent requests that fail with a transient error
 	pub async fn request(&self, info: RequestInfo) -> Result<HTTPResponse, String> {
-		match info.r#type {
-			RequestType::GET => self.get_request(info).await,
-			RequestType::POST => self.post_request(info).await,
-			RequestType::PUT => self.put_request(info).await
+		match self.send_with_retries(&info, true).await? {
+			Response::Full(response) => Ok(response),
+			Response::Streaming(_) => unreachable!("requested a full response")
 		}
 	}
 
-	async fn post_request(&self, info: RequestInfo) -> Result<HTTPResponse, String> {
-		let mut req = Client::new().post(info.url);
-		
-		if info.content_type.is_some() {
-			req = req.content_type(info.content_type.unwrap());
-		}
-		
-		match &self.headers {
-			Some(map) => {
-				for (key, val) in map {
-					req = req.header(key, val.to_owned());
-				}
-			}
-			None => {}
+	/// Sends the request and returns as soon as the response headers arrive,
+	/// the body is read from the returned stream
+	pub async fn request_stream(&self, info: RequestInfo) -> Result<StreamingResponse, String> {
+		match self.send_with_retries(&info, false).await? {
+			Response::Streaming(response) => Ok(response),
+			Response::Full(_) => unreachable!("requested a streaming response")
 		}
+	}
+
+	async fn send_with_retries(&self, info: &RequestInfo, read_body: bool) -> Result<Response, String> {
+		let retries = if info.is_idempotent() { self.config.retries } else { 0 };
+		let mut backoff = Backoff::new(self.config.initial_backoff, self.config.max_backoff).with_jitter();
+
+		loop {
+			let res = self.send(info, read_body).await;
+			let outcome = match &res {
+				Ok(response) => metrics::status_class(response.status_code()),
+				Err(_) => "error"
+			};
+			metrics::HTTP_CLIENT_REQUESTS.with_label_values(&[info.r#type.method().as_str(), outcome]).inc();
 
-		match info.data {
-			Some(str) => {
-				match req.send_body(&str).await {
-					Err(e) => Err(format!("Error making post request: {}", e)),
-					Ok(response) => Ok(HTTPResponse::from(response).await)
+			let error = match res {
+				Ok(response) if !is_transient_status(response.status_code()) => return Ok(response),
+				Ok(response) => {
+					if backoff.attempts() >= retries {
+						return Ok(response);
+					}
+					format!("status {}", response.status_code())
+				},
+				Err(RequestError::Fatal(e)) => return Err(e),
+				Err(RequestError::Transient(e)) => {
+					if backoff.attempts() >= retries {
+						return Err(e);
+					}
+					e
 				}
-			}
-			None => Err("A post request needs data to send".to_owned())
+			};
+
+			metrics::HTTP_CLIENT_RETRIES.with_label_values(&[info.r#type.method().as_str()]).inc();
+			let delay = backoff.next_delay();
+			warn!("{} request to {} failed with {}, retrying in {:?} ({}/{})", info.r#type, info.url, error, delay, backoff.attempts(), retries);
+			delay_for(delay).await;
 		}
 	}
 
-	async fn put_request(&self, info: RequestInfo) -> Result<HTTPResponse, String> {
-		let mut req = Client::new().put(info.url);
-		
+	#[allow(clippy::unnecessary_unwrap, clippy::single_match)]
+	async fn send(&self, info: &RequestInfo, read_body: bool) -> Result<Response, RequestError> {
+		let mut req = self.client().request(info.r#type.method(), info.full_url()?);
+
 		if info.content_type.is_some() {
-			req = req.content_type(info.content_type.unwrap());
+			req = req.content_type(info.content_type.as_ref().unwrap().as_str());
 		}
-		
+
 		match &self.headers {
 			Some(map) => {
 				for (key, val) in map {
@@ -72,35 +163,106 @@ impl HTTPClient {
 			None => {}
 		}
 
-		match info.data {
-			Some(str) => {
-				match req.send_body(&str).await {
-					Err(e) => Err(format!("Error making post request: {}", e)),
-					Ok(response) => Ok(HTTPResponse::from(response).await)
+		for (key, val) in &info.headers {
+			req = req.set_header(key.as_str(), val.to_owned());
+		}
+
+		let response = match &info.data {
+			None => req.send().await,
+			Some(RequestBody::Text(text)) => req.send_body(text.clone()).await,
+			Some(RequestBody::Bytes(bytes)) => req.send_body(bytes.clone()).await,
+			Some(RequestBody::Stream(stream)) => {
+				let stream = stream.borrow_mut().take();
+				match stream {
+					Some(stream) => req.send_stream(stream).await,
+					None => return Err(RequestError::Fatal("A streaming request body can only be sent once".to_owned()))
 				}
 			}
-			None => Err("A post request needs data to send".to_owned())
+		};
+
+		match response {
+			Ok(response) if read_body => HTTPResponse::from(response, self.config.max_response_size).await.map(Response::Full),
+			Ok(response) => Ok(Response::Streaming(StreamingResponse::from(response))),
+			Err(e) => Err(RequestError::from(e))
 		}
 	}
 
-	async fn get_request(&self, info: RequestInfo) -> Result<HTTPResponse, String> {
-		let mut req = Client::new().get(&info.url);
-		
-		if info.content_type.is_some() {
-			req = req.content_type(info.content_type.unwrap());
-		}
-		
-		match &self.headers {
-			Some(map) => {
-				for (key, val) in map {
-					req = req.header(key, val.to_owned());
-				}
+	/// Gets this client's connection pool for the current thread
+	fn client(&self) -> Client {
+		CLIENTS.with(|clients| {
+			let mut clients = clients.borrow_mut();
+			if let Some((_, client)) = clients.get(&self.id) {
+				return client.clone();
 			}
-			None => {}
+
+			// Only checked when a pool is made, which is rare enough that the scan doesn't matter
+			clients.retain(|_, (alive, _)| alive.strong_count() > 0);
+
+			let mut connector = Connector::new().timeout(self.config.connect_timeout);
+			if let Some(tls) = &self.config.tls {
+				connector = connector.rustls(tls.clone());
+			}
+
+			let client = Client::builder()
+				.connector(connector.finish())
+				.timeout(self.config.read_timeout)
+				.finish();
+			clients.insert(self.id, (Arc::downgrade(&self.alive), client.clone()));
+			client
+		})
+	}
+}
+
+fn is_transient_status(status_code: u16) -> bool {
+	status_code == 502 || status_code == 503 || status_code == 504
+}
+
+enum RequestError {
+	/// The request may succeed if it is sent again
+	Transient(String),
+	Fatal(String)
+}
+
+impl From<SendRequestError> for RequestError {
+	fn from(e: SendRequestError) -> Self {
+		match e {
+			SendRequestError::Connect(_) | SendRequestError::Send(_) | SendRequestError::Timeout | SendRequestError::H2(_) | SendRequestError::Response(_) =>
+				RequestError::Transient(format!("Error making request: {}", e)),
+			_ => RequestError::Fatal(format!("Error making request: {}", e))
 		}
+	}
+}
+
+pub type BodyStream = Pin<Box<dyn Stream<Item = Result<Bytes, actix_web::Error>>>>;
+
+pub enum RequestBody {
+	Text(String),
+	Bytes(Bytes),
+	/// A body that is streamed as it is sent, so it can't be retried
+	Stream(RefCell<Option<BodyStream>>)
+}
+
+impl From<String> for RequestBody {
+	fn from(text: String) -> Self {
+		RequestBody::Text(text)
+	}
+}
+
+impl From<Bytes> for RequestBody {
+	fn from(bytes: Bytes) -> Self {
+		RequestBody::Bytes(bytes)
+	}
+}
+
+impl From<Vec<u8>> for RequestBody {
+	fn from(bytes: Vec<u8>) -> Self {
+		RequestBody::Bytes(Bytes::from(bytes))
+	}
+}
 
-		let response = req.send().await.unwrap();
-		Ok(HTTPResponse::from(response).await)
+impl From<BodyStream> for RequestBody {
+	fn from(stream: BodyStream) -> Self {
+		RequestBody::Stream(RefCell::new(Some(stream)))
 	}
 }
 
@@ -108,72 +270,379 @@ pub struct RequestInfo {
 	url: String,
 	content_type: Option<String>,
 	r#type: RequestType,
-	data: Option<String>
+	data: Option<RequestBody>,
+	headers: Vec<(String, String)>,
+	query: Vec<(String, String)>,
+	idempotent: bool,
+	once: bool
 }
 
 impl RequestInfo {
-	pub fn get(path: String) -> Self {
+	pub fn new(r#type: RequestType, path: String) -> Self {
 		RequestInfo {
 			url: path,
-			r#type: RequestType::GET,
+			r#type,
 			data: None,
-			content_type: None
-		}
-	}
-	
-	pub fn post(path: String, data: String) -> Self {
-		RequestInfo {
-			url: path,
-			data: Some(data),
 			content_type: None,
-			r#type: RequestType::POST
+			headers: Vec::new(),
+			query: Vec::new(),
+			idempotent: false,
+			once: false
 		}
 	}
-	
-	pub fn put(path: String, data: String) -> Self {
-		RequestInfo {
-			url: path,
-			data: Some(data),
-			content_type: None,
-			r#type: RequestType::PUT
-		}
+
+	pub fn get(path: String) -> Self {
+		Self::new(RequestType::GET, path)
+	}
+
+	pub fn post<B: Into<RequestBody>>(path: String, data: B) -> Self {
+		Self::new(RequestType::POST, path).body(data)
+	}
+
+	pub fn put<B: Into<RequestBody>>(path: String, data: B) -> Self {
+		Self::new(RequestType::PUT, path).body(data)
+	}
+
+	pub fn delete(path: String) -> Self {
+		Self::new(RequestType::DELETE, path)
+	}
+
+	pub fn head(path: String) -> Self {
+		Self::new(RequestType::HEAD, path)
+	}
+
+	/// Copies the resource at `path` to `destination`, used by CouchDB to copy documents
+	pub fn copy(path: String, destination: String) -> Self {
+		Self::new(RequestType::COPY, path).header("Destination", &destination)
+	}
+
+	pub fn body<B: Into<RequestBody>>(mut self, data: B) -> Self {
+		self.data = Some(data.into());
+		self
 	}
-	
+
 	pub fn content_type(mut self, content_type: String) -> Self {
 		self.content_type = Some(content_type);
 		self
 	}
+
+	/// Sets a header for just this request, replacing any header the client sends by default
+	pub fn header(mut self, key: &str, value: &str) -> Self {
+		self.headers.push((key.to_owned(), value.to_owned()));
+		self
+	}
+
+	/// Adds a url-encoded query parameter to the url
+	pub fn query(mut self, key: &str, value: &str) -> Self {
+		self.query.push((key.to_owned(), value.to_owned()));
+		self
+	}
+
+	/// Marks a request that is safe to retry even though its method isn't,
+	/// like a POST to a read-only endpoint
+	pub fn idempotent(mut self) -> Self {
+		self.idempotent = true;
+		self
+	}
+
+	/// Never retries this request even though its method is idempotent,
+	/// for writes that only succeed once like a PUT carrying a `_rev`
+	pub fn once(mut self) -> Self {
+		self.once = true;
+		self
+	}
+
+	/// A copy of this request to send again, `None` for streamed bodies that can only be sent once
+	pub fn try_clone(&self) -> Option<Self> {
+		let data = match &self.data {
+			Some(RequestBody::Text(text)) => Some(RequestBody::Text(text.clone())),
+			Some(RequestBody::Bytes(bytes)) => Some(RequestBody::Bytes(bytes.clone())),
+			Some(RequestBody::Stream(_)) => return None,
+			None => None
+		};
+
+		Some(RequestInfo {
+			url: self.url.clone(),
+			content_type: self.content_type.clone(),
+			r#type: self.r#type.clone(),
+			data,
+			headers: self.headers.clone(),
+			query: self.query.clone(),
+			idempotent: self.idempotent,
+			once: self.once
+		})
+	}
+
+	fn is_idempotent(&self) -> bool {
+		let streaming = matches!(self.data, Some(RequestBody::Stream(_)));
+		!streaming && !self.once && (self.idempotent || self.r#type.is_idempotent())
+	}
+
+	fn full_url(&self) -> Result<String, RequestError> {
+		if self.query.is_empty() {
+			return Ok(self.url.clone());
+		}
+
+		match serde_urlencoded::to_string(&self.query) {
+			Ok(query) => Ok(format!("{}{}{}", self.url, if self.url.contains('?') { '&' } else { '?' }, query)),
+			Err(e) => Err(RequestError::Fatal(format!("Error encoding query: {}", e)))
+		}
+	}
 }
 
+#[derive(Debug, Clone)]
 pub enum RequestType {
 	GET,
 	POST,
-	PUT
+	PUT,
+	DELETE,
+	HEAD,
+	COPY,
+	Other(Method)
+}
+
+impl RequestType {
+	fn method(&self) -> Method {
+		match self {
+			RequestType::GET => Method::GET,
+			RequestType::POST => Method::POST,
+			RequestType::PUT => Method::PUT,
+			RequestType::DELETE => Method::DELETE,
+			RequestType::HEAD => Method::HEAD,
+			RequestType::COPY => Method::from_bytes(b"COPY").unwrap(),
+			RequestType::Other(method) => method.clone()
+		}
+	}
+
+	pub fn is_idempotent(&self) -> bool {
+		match self {
+			RequestType::GET | RequestType::PUT | RequestType::DELETE | RequestType::HEAD => true,
+			RequestType::POST | RequestType::COPY => false,
+			RequestType::Other(method) => method.is_idempotent()
+		}
+	}
+}
+
+impl fmt::Display for RequestType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.method())
+	}
+}
+
+enum Response {
+	Full(HTTPResponse),
+	Streaming(StreamingResponse)
+}
+
+impl Response {
+	fn status_code(&self) -> u16 {
+		match self {
+			Response::Full(response) => response.status_code,
+			Response::Streaming(response) => response.status_code
+		}
+	}
 }
 
 pub struct HTTPResponse {
-	pub body: String,
+	pub body: Bytes,
+	pub headers: HeaderMap,
+	pub status_code: u16
+}
+
+impl HTTPResponse {
+	/// The body as UTF-8 text
+	pub fn text(&self) -> Result<&str, String> {
+		std::str::from_utf8(&self.body).map_err(|e| format!("Response body is not valid UTF-8: {}", e))
+	}
+
+	pub fn json<T: DeserializeOwned>(&self) -> Result<T, String> {
+		serde_json::from_slice(&self.body).map_err(|e| format!("Error deserializing response: {}", e))
+	}
+}
+
+pub struct StreamingResponse {
+	pub body: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>>,
 	pub headers: HeaderMap,
 	pub status_code: u16
 }
 
 // I hate ClientResponse....
-use std::pin::Pin;
-use std::boxed::Box;
-use tokio::stream::Stream;
-use actix_web::web::Bytes;
-use actix_http::encoding::Decoder;
-use actix_http::error::PayloadError;
-use actix_http::Payload;
+type RawResponse = ClientResponse<Decoder<Payload<Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>>>>>;
 
 impl HTTPResponse {
-	pub async fn from(mut response: ClientResponse<Decoder<Payload<Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>>>>>) -> Self {
-		let body: actix_web::web::Bytes = response.body().await.unwrap();
-		let headers = response.headers();
-		HTTPResponse {
-			headers: headers.to_owned(),
-			body: String::from_utf8(body.to_vec()).unwrap(),
-			status_code: response.status().as_u16()
+	async fn from(mut response: RawResponse, limit: usize) -> Result<Self, RequestError> {
+		match response.body().limit(limit).await {
+			Ok(body) => Ok(HTTPResponse {
+				headers: response.headers().to_owned(),
+				body,
+				status_code: response.status().as_u16()
+			}),
+			// Sending it again won't make the body any smaller
+			Err(e @ PayloadError::Overflow) => Err(RequestError::Fatal(format!("Error reading response body: {}", e))),
+			Err(e) => Err(RequestError::Transient(format!("Error reading response body: {}", e)))
 		}
 	}
-}
\ No newline at end of file
+}
+
+impl StreamingResponse {
+	fn from(response: RawResponse) -> Self {
+		StreamingResponse {
+			headers: response.headers().to_owned(),
+			status_code: response.status().as_u16(),
+			body: Box::pin(response)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::TcpListener;
+	use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+	use actix_web::dev::Server;
+	use futures::stream;
+	use super::*;
+
+	fn stream_body() -> RequestBody {
+		let chunks: BodyStream = Box::pin(stream::iter(vec![Ok(Bytes::from_static(b"chunk"))]));
+		RequestBody::from(chunks)
+	}
+
+	#[test]
+	fn methods_are_idempotent_as_in_the_http_spec() {
+		for method in &[RequestType::GET, RequestType::PUT, RequestType::DELETE, RequestType::HEAD, RequestType::Other(Method::OPTIONS)] {
+			assert!(method.is_idempotent(), "{} should be idempotent", method);
+		}
+		for method in &[RequestType::POST, RequestType::COPY, RequestType::Other(Method::PATCH)] {
+			assert!(!method.is_idempotent(), "{} shouldn't be idempotent", method);
+		}
+	}
+
+	#[test]
+	fn only_idempotent_requests_are_retried() {
+		assert!(RequestInfo::get("/".to_owned()).is_idempotent());
+		assert!(!RequestInfo::post("/".to_owned(), "{}".to_owned()).is_idempotent());
+		assert!(RequestInfo::post("/_find".to_owned(), "{}".to_owned()).idempotent().is_idempotent());
+		assert!(!RequestInfo::put("/doc".to_owned(), "{}".to_owned()).once().is_idempotent());
+		assert!(!RequestInfo::put("/doc".to_owned(), stream_body()).is_idempotent());
+	}
+
+	#[test]
+	fn full_url_encodes_the_query() {
+		assert_eq!(RequestInfo::get("http://couch/db".to_owned()).full_url().ok(), Some("http://couch/db".to_owned()));
+		assert_eq!(RequestInfo::get("http://couch/db".to_owned()).query("since", "1-a b&c=d").full_url().ok(), Some("http://couch/db?since=1-a+b%26c%3Dd".to_owned()));
+		assert_eq!(RequestInfo::get("http://couch/db/_changes?feed=longpoll".to_owned()).query("since", "now").query("timeout", "5").full_url().ok(),
+			Some("http://couch/db/_changes?feed=longpoll&since=now&timeout=5".to_owned()));
+	}
+
+	#[test]
+	fn gateway_errors_are_transient() {
+		assert!(is_transient_status(502) && is_transient_status(503) && is_transient_status(504));
+		assert!(!is_transient_status(500) && !is_transient_status(429) && !is_transient_status(409));
+		assert!(matches!(RequestError::from(SendRequestError::Timeout), RequestError::Transient(_)));
+		assert!(matches!(RequestError::from(SendRequestError::TunnelNotSupported), RequestError::Fatal(_)));
+	}
+
+	#[test]
+	fn streamed_requests_can_not_be_cloned() {
+		let request = RequestInfo::put("/doc".to_owned(), "{}".to_owned()).header("If-Match", "1-a").query("batch", "ok");
+		let clone = request.try_clone().unwrap();
+		assert_eq!(clone.full_url().ok(), request.full_url().ok());
+		assert_eq!(clone.headers, request.headers);
+		assert!(RequestInfo::put("/doc".to_owned(), stream_body()).try_clone().is_none());
+	}
+
+	/// Counts requests to `/flaky`, which fails with a 503 until the `ok_after`th request
+	struct Flaky {
+		hits: AtomicUsize,
+		ok_after: usize
+	}
+
+	async fn flaky(flaky: web::Data<Flaky>, _body: Bytes) -> HttpResponse {
+		if flaky.hits.fetch_add(1, Ordering::SeqCst) + 1 >= flaky.ok_after {
+			HttpResponse::Ok().finish()
+		} else {
+			HttpResponse::ServiceUnavailable().finish()
+		}
+	}
+
+	/// Answers with the query string and every `X-Test` header
+	async fn echo(req: HttpRequest) -> HttpResponse {
+		let headers: Vec<&str> = req.headers().get_all("X-Test").filter_map(|value| value.to_str().ok()).collect();
+		HttpResponse::Ok().body(format!("{} {}", req.query_string(), headers.join(",")))
+	}
+
+	fn start_server(ok_after: usize) -> (Server, String, web::Data<Flaky>) {
+		let flaky_state = web::Data::new(Flaky { hits: AtomicUsize::new(0), ok_after });
+		let state = flaky_state.clone();
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+		let server = HttpServer::new(move || App::new()
+				.app_data(state.clone())
+				.route("/flaky", web::to(flaky))
+				.route("/echo", web::get().to(echo)))
+			.workers(1)
+			.listen(listener)
+			.unwrap()
+			.run();
+		(server, url, flaky_state)
+	}
+
+	fn client(retries: u32) -> HTTPClient {
+		HTTPClient::new().config(ClientConfig {
+			retries,
+			initial_backoff: Duration::from_millis(1),
+			max_backoff: Duration::from_millis(1),
+			..ClientConfig::default()
+		})
+	}
+
+	#[actix_rt::test]
+	async fn retries_idempotent_requests_until_they_succeed() {
+		let (server, url, flaky) = start_server(3);
+		let res = client(3).request(RequestInfo::get(format!("{}/flaky", url))).await;
+		assert_eq!(res.map(|res| res.status_code).ok(), Some(200));
+		assert_eq!(flaky.hits.load(Ordering::SeqCst), 3);
+		server.stop(false).await;
+	}
+
+	#[actix_rt::test]
+	async fn gives_up_after_the_retries() {
+		let (server, url, flaky) = start_server(usize::MAX);
+		let res = client(2).request(RequestInfo::get(format!("{}/flaky", url))).await;
+		assert_eq!(res.map(|res| res.status_code).ok(), Some(503));
+		assert_eq!(flaky.hits.load(Ordering::SeqCst), 3);
+		server.stop(false).await;
+	}
+
+	#[actix_rt::test]
+	async fn sends_other_requests_once() {
+		let (server, url, flaky) = start_server(usize::MAX);
+		let client = client(3);
+		let requests = vec![
+			RequestInfo::post(format!("{}/flaky", url), "{}".to_owned()),
+			RequestInfo::put(format!("{}/flaky", url), "{}".to_owned()).once(),
+			RequestInfo::put(format!("{}/flaky", url), stream_body())
+		];
+		for request in requests {
+			assert_eq!(client.request(request).await.map(|res| res.status_code).ok(), Some(503));
+		}
+		assert_eq!(flaky.hits.load(Ordering::SeqCst), 3);
+
+		let res = client.request(RequestInfo::post(format!("{}/flaky", url), "{}".to_owned()).idempotent()).await;
+		assert_eq!(res.map(|res| res.status_code).ok(), Some(503));
+		assert_eq!(flaky.hits.load(Ordering::SeqCst), 7);
+		server.stop(false).await;
+	}
+
+	#[actix_rt::test]
+	async fn request_headers_replace_client_headers() {
+		let (server, url, _) = start_server(1);
+		let client = HTTPClient::with_headers(vec![("X-Test".to_owned(), "client".to_owned())].into_iter().collect());
+
+		let res = client.request(RequestInfo::get(format!("{}/echo", url)).query("q", "a b")).await.unwrap();
+		assert_eq!(res.text(), Ok("q=a+b client"));
+		let res = client.request(RequestInfo::get(format!("{}/echo", url)).header("X-Test", "request")).await.unwrap();
+		assert_eq!(res.text(), Ok(" request"));
+		server.stop(false).await;
+	}
+}
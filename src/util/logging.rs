@@ -4,43 +4,186 @@
 
 use std::io::Write;
 use std::error::Error;
-use std::sync::Arc;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
 use std::io::Stdout;
 use std::fmt;
 use chrono::Local;
-use log::{LevelFilter, Level};
-use log4rs::append::file::FileAppender;
+use log::Level;
+use log4rs::append::rolling_file::{LogFile, RollingFileAppender};
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::rolling_file::policy::compound::trigger::Trigger;
 use log4rs::append::Append;
+use log4rs::encode::Encode;
+use log4rs::encode::json::JsonEncoder;
 use log4rs::encode::pattern::{PatternEncoder};
 use log4rs::config::{Appender, Config, Root};
 use log4rs::filter::{Filter, Response};
 use log::*;
+use serde::Serialize;
+use schemars::JsonSchema;
 
 #[cfg(unix)]
 use termion::color;
 
-#[cfg(debug_assertions)]
-const LEVEL_FILTER: LevelFilter = LevelFilter::Debug;
-#[cfg(not(debug_assertions))]
-const LEVEL_FILTER: LevelFilter = LevelFilter::Info;
+use crate::config::LogConfig;
 
-pub fn init_logging() {
-	let logfile = FileAppender::builder().encoder(Box::new(PatternEncoder::new("[{d(%H:%M:%S)} {l}]: {m}\n"))).build("log/latest.log").unwrap();
+const CRATE_NAME: &str = env!("CARGO_CRATE_NAME");
+
+/// The MDC key holding the id of the request being served, see `with_request_id`
+pub const REQUEST_ID_KEY: &str = "request_id";
+
+pub fn init_logging(config: &LogConfig) -> LogLevels {
+	let levels = LogLevels::from_config(config);
+	let encoder: Box<dyn Encode> = if config.is_json() {
+		Box::new(JsonEncoder::new())
+	} else {
+		Box::new(PatternEncoder::new(&format!("[{{d(%Y-%m-%d %H:%M:%S)}} {{l}} {{X({})(-)}}]: {{m}}{{n}}", REQUEST_ID_KEY)))
+	};
+	let roller = FixedWindowRoller::builder().base(1).build(&archive_pattern(&config.file), config.keep_files).unwrap();
+	let trigger = RotationTrigger::new(&config.file, config.max_file_size, config.rotate_after());
+	let policy = CompoundPolicy::new(Box::new(trigger), Box::new(roller));
+	let logfile = RollingFileAppender::builder().encoder(encoder).build(&config.file, Box::new(policy)).unwrap();
 	let console = CustomConsoleAppender { console_interface: Arc::new(std::io::stdout())};
 
 	let config = Config::builder()
-			.appender(Appender::builder().filter(Box::new(CrateFilter::new("modolumia"))).build("console", Box::new(console)))
-			.appender(Appender::builder().filter(Box::new(CrateFilter::new("modolumia"))).build("logfile", Box::new(logfile)))
+			.appender(Appender::builder().filter(Box::new(levels.clone())).build("console", Box::new(console)))
+			.appender(Appender::builder().filter(Box::new(levels.clone())).build("logfile", Box::new(logfile)))
 			.build(
 				Root::builder()
 					.appender("console")
 					.appender("logfile")
-					.build(LEVEL_FILTER)
+					.build(LevelFilter::Trace)
 			).unwrap();
 
 	log4rs::init_config(config).unwrap();
+	// log4rs lets everything through to the filters, so put the real maximum back
+	levels.update_max_level();
 	info!("Initialized loggging");
 	debug!("Currently running in a debug environment");
+	levels
+}
+
+/// Sends panics to the log instead of stderr, with the id of the request that panicked when there is one
+pub fn install_panic_hook() {
+	std::panic::set_hook(Box::new(|info| {
+		let message = match info.payload().downcast_ref::<&str>() {
+			Some(message) => message.to_string(),
+			None => match info.payload().downcast_ref::<String>() {
+				Some(message) => message.clone(),
+				None => "Box<dyn Any>".to_owned()
+			}
+		};
+		let location = info.location().map(|location| location.to_string()).unwrap_or_else(|| "an unknown location".to_owned());
+		let thread = std::thread::current();
+		let thread = thread.name().unwrap_or("<unnamed>");
+
+		match log_mdc::get(REQUEST_ID_KEY, |id| id.map(str::to_owned)) {
+			Some(id) => error!("Thread {} panicked while serving request {} at {}: {}", thread, id, location, message),
+			None => error!("Thread {} panicked at {}: {}", thread, location, message)
+		}
+	}));
+}
+
+/// Writes out anything the appenders are still holding, called before exiting
+pub fn flush_logs() {
+	log::logger().flush();
+}
+
+/// Rolled over logs sit next to the log file, `log/latest.log` rolls over to `log/latest.1.log.gz`
+fn archive_pattern(file: &Path) -> String {
+	let stem = file.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+	let name = match file.extension() {
+		Some(ext) => format!("{}.{{}}.{}.gz", stem, ext.to_string_lossy()),
+		None => format!("{}.{{}}.gz", stem)
+	};
+	file.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Rolls the log file over once it gets too big or too old
+struct RotationTrigger {
+	max_size: u64,
+	max_age: Option<Duration>,
+	started: Mutex<SystemTime>
+}
+
+impl RotationTrigger {
+	fn new(file: &Path, max_size: u64, max_age: Option<Duration>) -> Self {
+		// Carry on from an existing log file's age so restarts don't keep pushing rotation back
+		let started = std::fs::metadata(file).and_then(|meta| meta.created()).unwrap_or_else(|_| SystemTime::now());
+		RotationTrigger {
+			max_size,
+			max_age,
+			started: Mutex::new(started)
+		}
+	}
+}
+
+impl Trigger for RotationTrigger {
+	fn trigger(&self, file: &LogFile) -> Result<bool, Box<dyn Error + Sync + Send>> {
+		if self.max_size > 0 && file.len_estimate() > self.max_size {
+			*self.started.lock().unwrap() = SystemTime::now();
+			return Ok(true);
+		}
+
+		if let Some(max_age) = self.max_age {
+			let mut started = self.started.lock().unwrap();
+			if started.elapsed().map(|age| age >= max_age).unwrap_or(false) {
+				*started = SystemTime::now();
+				return Ok(true);
+			}
+		}
+
+		Ok(false)
+	}
+}
+
+impl fmt::Debug for RotationTrigger {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		write!(f, "RotationTrigger({} bytes, {:?})", self.max_size, self.max_age)
+	}
+}
+
+/// Runs a future with `REQUEST_ID_KEY` set in the MDC every time it's polled,
+/// so everything logged while serving a request carries its id
+pub fn with_request_id<F: Future>(request_id: String, future: F) -> WithRequestId<F> {
+	WithRequestId {
+		request_id,
+		inner: Box::pin(future)
+	}
+}
+
+pub struct WithRequestId<F> {
+	request_id: String,
+	inner: Pin<Box<F>>
+}
+
+impl<F: Future> Future for WithRequestId<F> {
+	type Output = F::Output;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		let WithRequestId { request_id, inner } = &mut *self;
+		in_request(request_id, || inner.as_mut().poll(cx))
+	}
+}
+
+/// Runs `f` with `REQUEST_ID_KEY` set in the MDC, putting back whatever was there before
+pub fn in_request<R>(request_id: &str, f: impl FnOnce() -> R) -> R {
+	let previous = log_mdc::insert(REQUEST_ID_KEY, request_id);
+	let res = f();
+	if let Some(previous) = previous {
+		log_mdc::insert(REQUEST_ID_KEY, previous);
+	} else {
+		log_mdc::remove(REQUEST_ID_KEY);
+	}
+	res
 }
 
 struct CustomConsoleAppender {
@@ -57,18 +200,25 @@ impl Append for CustomConsoleAppender {
 			Level::Debug => write!(writer, "{}", color::Fg(color::LightCyan))?,
 			_ => write!(writer, "{}", color::Fg(color::Reset))?,
 		}
-		writeln!(writer, "[{} {}]: {}{}", Local::now().format("%H:%M:%S"), record.metadata().level(), record.args(), color::Fg(color::Reset))?;
+		writeln!(writer, "[{} {}{}]: {}{}", Local::now().format("%H:%M:%S"), record.metadata().level(), request_id_suffix(), record.args(), color::Fg(color::Reset))?;
 		Ok(())
 	}
 
 	#[cfg(not(unix))]
 	fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Sync + Send>> {
 		let mut writer = self.console_interface.lock();
-		writeln!(writer, "[{} {}]: {}", Local::now().format("%H:%M:%S"), record.metadata().level(), record.args())?;
+		writeln!(writer, "[{} {}{}]: {}", Local::now().format("%H:%M:%S"), record.metadata().level(), request_id_suffix(), record.args())?;
 		Ok(())
 	}
 
-	fn flush(&self) { }
+	fn flush(&self) {
+		let _ = self.console_interface.lock().flush();
+	}
+}
+
+/// ` <id>` while serving a request, so console lines only get longer when there is an id to show
+fn request_id_suffix() -> String {
+	log_mdc::get(REQUEST_ID_KEY, |id| id.map(|id| format!(" {}", id))).unwrap_or_default()
 }
 
 impl fmt::Debug for CustomConsoleAppender {
@@ -77,55 +227,100 @@ impl fmt::Debug for CustomConsoleAppender {
 	}
 }
 
-#[cfg(debug_assertions)]
-struct CrateFilter {
-    filter: String
+/// The level each module logs at, shared with the admin endpoint so levels can change without a restart.<br>
+/// The most specific entry in `modules` wins, then modolumia's own level or the dependency level
+#[derive(Clone)]
+pub struct LogLevels {
+	table: Arc<RwLock<LevelTable>>
 }
 
-#[cfg(not(debug_assertions))]
-struct CrateFilter;
-
-impl CrateFilter {
-    #[cfg(debug_assertions)]
-    pub fn new(filter: &str) -> Self {
-        CrateFilter {
-            filter: filter.to_owned()
-        }
-    }
+struct LevelTable {
+	level: LevelFilter,
+	dependency_level: LevelFilter,
+	modules: BTreeMap<String, LevelFilter>
+}
 
-    #[cfg(not(debug_assertions))]
-    pub fn new(_filter: &str) -> Self {
-        CrateFilter
-    }
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct LogLevelsInfo {
+	pub level: String,
+	pub dependency_level: String,
+	pub modules: BTreeMap<String, String>
 }
 
-impl Filter for CrateFilter {
-    #[cfg(debug_assertions)]
-    fn filter(&self, record: &Record) -> Response {
-        if record.level() != Level::Debug && record.level() != Level::Trace {
-            return Response::Accept;
-        }
+impl LogLevels {
+	pub fn from_config(config: &LogConfig) -> Self {
+		let modules = config.modules.iter()
+			.filter_map(|(module, level)| LevelFilter::from_str(level).ok().map(|level| (module.clone(), level)))
+			.collect();
+
+		LogLevels {
+			table: Arc::new(RwLock::new(LevelTable {
+				level: config.level_filter(),
+				dependency_level: config.dependency_level_filter(),
+				modules
+			}))
+		}
+	}
+
+	pub fn level_for(&self, target: &str) -> LevelFilter {
+		let table = self.table.read().unwrap();
+		let module = table.modules.iter()
+			.filter(|(module, _)| is_under(target, module))
+			.max_by_key(|(module, _)| module.len());
 
-        match record.module_path() {
-            Some(path) => {
-                if path.starts_with(&self.filter) {
-                    Response::Accept
-                } else {
-                    Response::Reject
-                }
-            },
-            None => Response::Reject
-        }
-    }
+		match module {
+			Some((_, level)) => *level,
+			None if is_under(target, CRATE_NAME) => table.level,
+			None => table.dependency_level
+		}
+	}
 
-    #[cfg(not(debug_assertions))]
-    fn filter(&self, _record: &Record) -> Response {
-        Response::Neutral
-    }
+	/// Sets the level for a module and everything under it, `None` goes back to the default
+	pub fn set_module(&self, module: &str, level: Option<LevelFilter>) {
+		{
+			let mut table = self.table.write().unwrap();
+			match level {
+				Some(level) => { table.modules.insert(module.to_owned(), level); },
+				None => { table.modules.remove(module); }
+			}
+		}
+		self.update_max_level();
+	}
+
+	pub fn info(&self) -> LogLevelsInfo {
+		let table = self.table.read().unwrap();
+		LogLevelsInfo {
+			level: table.level.to_string(),
+			dependency_level: table.dependency_level.to_string(),
+			modules: table.modules.iter().map(|(module, level)| (module.clone(), level.to_string())).collect()
+		}
+	}
+
+	/// Lets `log` skip records no module would log before they get to log4rs
+	fn update_max_level(&self) {
+		let table = self.table.read().unwrap();
+		let max = table.modules.values().copied().chain(vec![table.level, table.dependency_level]).max().unwrap_or(LevelFilter::Off);
+		log::set_max_level(max);
+	}
+}
+
+/// Whether `target` is `module` or one of its submodules
+fn is_under(target: &str, module: &str) -> bool {
+	target.strip_prefix(module).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
 }
 
-impl fmt::Debug for CrateFilter {
-    fn fmt(&self, _f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        Ok(())
-    }
-}
\ No newline at end of file
+impl Filter for LogLevels {
+	fn filter(&self, record: &Record) -> Response {
+		if record.level() <= self.level_for(record.target()) {
+			Response::Neutral
+		} else {
+			Response::Reject
+		}
+	}
+}
+
+impl fmt::Debug for LogLevels {
+	fn fmt(&self, _f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		Ok(())
+	}
+}
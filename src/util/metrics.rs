@@ -0,0 +1,87 @@
+use lazy_static::lazy_static;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+
+// Upload sizes go from small JSON bodies up to the 32MiB default file limit
+const SIZE_BUCKETS: [f64; 8] = [256.0, 1024.0, 16384.0, 65536.0, 262_144.0, 1_048_576.0, 8_388_608.0, 33_554_432.0];
+
+lazy_static! {
+	pub static ref REGISTRY: Registry = Registry::new_custom(Some("modolumia".to_owned()), None).unwrap();
+
+	/// Requests served, labelled with the matched route pattern rather than the path
+	pub static ref HTTP_REQUESTS: IntCounterVec = register(IntCounterVec::new(
+		Opts::new("http_requests_total", "Requests served"),
+		&["method", "route", "status"]
+	));
+	pub static ref HTTP_REQUEST_DURATION: HistogramVec = register(HistogramVec::new(
+		HistogramOpts::new("http_request_duration_seconds", "Time taken to serve a request"),
+		&["method", "route", "status"]
+	));
+	pub static ref HTTP_REQUESTS_IN_FLIGHT: IntGauge = register(IntGauge::new("http_requests_in_flight", "Requests currently being served"));
+	/// Request body sizes from `Content-Length`, only for requests that send a body
+	pub static ref UPLOAD_SIZE: HistogramVec = register(HistogramVec::new(
+		HistogramOpts::new("http_upload_size_bytes", "Size of request bodies").buckets(SIZE_BUCKETS.to_vec()),
+		&["route"]
+	));
+
+	/// Calls `DBManager` made, `database` is `none` for server wide calls like `_up`
+	pub static ref COUCHDB_REQUEST_DURATION: HistogramVec = register(HistogramVec::new(
+		HistogramOpts::new("couchdb_request_duration_seconds", "Time taken by CouchDB calls, including retries"),
+		&["database", "operation"]
+	));
+	/// Failed CouchDB calls, `kind` is `unavailable` when the circuit breaker rejected the call,
+	/// `request` when it couldn't be sent and `status` when CouchDB answered with a 5xx
+	pub static ref COUCHDB_ERRORS: IntCounterVec = register(IntCounterVec::new(
+		Opts::new("couchdb_errors_total", "CouchDB calls that failed"),
+		&["database", "operation", "kind"]
+	));
+	pub static ref COUCHDB_SESSIONS: IntGauge = register(IntGauge::new("couchdb_sessions_active", "CouchDB session cookies currently held"));
+
+	/// Outgoing requests by every `HTTPClient`, counting each retry on its own
+	pub static ref HTTP_CLIENT_REQUESTS: IntCounterVec = register(IntCounterVec::new(
+		Opts::new("http_client_requests_total", "Requests sent by the HTTP client"),
+		&["method", "outcome"]
+	));
+	pub static ref HTTP_CLIENT_RETRIES: IntCounterVec = register(IntCounterVec::new(
+		Opts::new("http_client_retries_total", "Requests the HTTP client retried"),
+		&["method"]
+	));
+
+	/// Requests turned away with a 429, by rate limit group
+	pub static ref RATE_LIMITED: IntCounterVec = register(IntCounterVec::new(
+		Opts::new("rate_limited_total", "Requests rejected by a rate limit"),
+		&["group"]
+	));
+
+	/// Lookups in the `DBManager` read cache, `result` is `hit` or `miss`
+	pub static ref CACHE_LOOKUPS: IntCounterVec = register(IntCounterVec::new(
+		Opts::new("cache_lookups_total", "Lookups in the CouchDB read cache"),
+		&["cache", "result"]
+	));
+}
+
+fn register<C: prometheus::core::Collector + Clone + 'static>(collector: Result<C, prometheus::Error>) -> C {
+	let collector = collector.unwrap();
+	REGISTRY.register(Box::new(collector.clone())).unwrap();
+	collector
+}
+
+/// Groups a status code into `2xx`, `4xx`, ... so labels don't explode
+pub fn status_class(status: u16) -> &'static str {
+	match status {
+		100..=199 => "1xx",
+		200..=299 => "2xx",
+		300..=399 => "3xx",
+		400..=499 => "4xx",
+		_ => "5xx"
+	}
+}
+
+/// Everything in the registry in the Prometheus text format
+pub fn gather() -> Result<String, String> {
+	let mut buffer = Vec::new();
+	if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+		return Err(format!("Error encoding metrics: {}", e));
+	}
+
+	String::from_utf8(buffer).map_err(|e| format!("Metrics were not valid UTF-8: {}", e))
+}
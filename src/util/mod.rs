@@ -1,2 +1,11 @@
+pub mod backoff;
+pub mod base62;
+pub mod compare;
 pub mod http_client;
-pub mod logging;
\ No newline at end of file
+pub mod logging;
+pub mod metrics;
+pub mod slug;
+pub mod tls;
+pub mod uuid;
+
+pub use self::uuid::Uuid;
\ No newline at end of file
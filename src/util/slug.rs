@@ -0,0 +1,53 @@
+const MAX_SLUG_LENGTH: usize = 60;
+
+/// Turns a name into something that reads well in a url, `My Cool Palette!` becomes `my-cool-palette`.<br>
+/// Anything that isn't an ascii letter or digit becomes a dash, so a name can give an empty slug
+pub fn slugify(name: &str) -> String {
+	let mut slug = String::with_capacity(name.len().min(MAX_SLUG_LENGTH));
+	for c in name.chars() {
+		if c.is_ascii_alphanumeric() {
+			slug.push(c.to_ascii_lowercase());
+		} else if !slug.is_empty() && !slug.ends_with('-') {
+			slug.push('-');
+		}
+
+		if slug.len() >= MAX_SLUG_LENGTH {
+			break;
+		}
+	}
+
+	slug.trim_end_matches('-').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn slugifies_names() {
+		assert_eq!(slugify("My Cool Palette!"), "my-cool-palette");
+		assert_eq!(slugify("  --Retro   80s--  "), "retro-80s");
+		assert_eq!(slugify("snake_case"), "snake-case");
+	}
+
+	#[test]
+	fn non_ascii_becomes_dashes() {
+		assert_eq!(slugify("Café Ünïcode"), "caf-n-code");
+		assert_eq!(slugify("日本 Pack"), "pack");
+	}
+
+	#[test]
+	fn names_without_letters_give_empty_slugs() {
+		assert_eq!(slugify(""), "");
+		assert_eq!(slugify("!!!"), "");
+		assert_eq!(slugify("日本語"), "");
+	}
+
+	#[test]
+	fn long_names_are_cut_off() {
+		assert_eq!(slugify(&"a".repeat(100)).len(), MAX_SLUG_LENGTH);
+		// Never ends on a dash, even when cut off right after one
+		let name = format!("{} b", "a".repeat(MAX_SLUG_LENGTH - 1));
+		assert_eq!(slugify(&name), "a".repeat(MAX_SLUG_LENGTH - 1));
+	}
+}
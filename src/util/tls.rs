@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError};
+use rustls::internal::pemfile;
+
+/// How `HTTPClient` verifies servers and identifies itself over TLS
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+	/// PEM bundle of CAs to trust instead of the public web roots
+	pub ca_bundle: Option<PathBuf>,
+	/// PEM certificate chain sent to the server for mutual TLS
+	pub client_cert: Option<PathBuf>,
+	/// PEM private key (PKCS#8 or RSA) for `client_cert`
+	pub client_key: Option<PathBuf>,
+	/// Skip certificate verification entirely, only allowed in debug builds
+	pub insecure: bool
+}
+
+impl TlsOptions {
+	pub fn build(&self) -> Result<ClientConfig, String> {
+		let mut config = ClientConfig::new();
+		config.set_protocols(&[b"http/1.1".to_vec()]);
+
+		match &self.ca_bundle {
+			Some(path) => {
+				let (valid, invalid) = config.root_store.add_pem_file(&mut open(path)?)
+					.map_err(|_| format!("Error reading CA bundle {}", path.display()))?;
+				if valid == 0 {
+					return Err(format!("CA bundle {} has no valid certificates", path.display()));
+				}
+				if invalid > 0 {
+					warn!("Skipped {} invalid certificates in CA bundle {}", invalid, path.display());
+				}
+			},
+			None => config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS)
+		}
+
+		match (&self.client_cert, &self.client_key) {
+			(Some(cert), Some(key)) => {
+				let certs = pemfile::certs(&mut open(cert)?)
+					.map_err(|_| format!("Error reading client certificate {}", cert.display()))?;
+				let key = load_key(key)?;
+				config.set_single_client_cert(certs, key)
+					.map_err(|e| format!("Error using client certificate: {}", e))?;
+			},
+			(None, None) => {},
+			_ => return Err("Mutual TLS needs both a client certificate and a client key".to_owned())
+		}
+
+		if self.insecure {
+			Self::disable_verification(&mut config)?;
+		}
+
+		Ok(config)
+	}
+
+	#[cfg(debug_assertions)]
+	fn disable_verification(config: &mut ClientConfig) -> Result<(), String> {
+		warn!("TLS certificate verification is disabled, never do this outside of development");
+		config.dangerous().set_certificate_verifier(std::sync::Arc::new(NoVerification));
+		Ok(())
+	}
+
+	#[cfg(not(debug_assertions))]
+	fn disable_verification(_config: &mut ClientConfig) -> Result<(), String> {
+		Err("Insecure TLS is only allowed in development builds".to_owned())
+	}
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, String> {
+	match File::open(path) {
+		Ok(file) => Ok(BufReader::new(file)),
+		Err(e) => Err(format!("Error opening {}: {}", path.display(), e))
+	}
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, String> {
+	let pkcs8 = pemfile::pkcs8_private_keys(&mut open(path)?).unwrap_or_default();
+	let rsa = pemfile::rsa_private_keys(&mut open(path)?).unwrap_or_default();
+
+	match pkcs8.into_iter().chain(rsa).next() {
+		Some(key) => Ok(key),
+		None => Err(format!("No private key found in {}", path.display()))
+	}
+}
+
+#[cfg_attr(not(debug_assertions), allow(dead_code))]
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+	fn verify_server_cert(&self, _roots: &RootCertStore, _presented_certs: &[Certificate], _dns_name: webpki::DNSNameRef, _ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+		Ok(ServerCertVerified::assertion())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::TcpListener;
+	use actix_web::{web, App, HttpResponse, HttpServer};
+	use actix_web::dev::Server;
+	use rustls::{NoClientAuth, ServerConfig};
+	use super::*;
+	use crate::util::http_client::{ClientConfig as HttpConfig, HTTPClient, RequestInfo};
+
+	fn fixture(name: &str) -> PathBuf {
+		Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/tls").join(name)
+	}
+
+	/// An https server for `localhost` with a certificate signed by `tests/tls/ca.pem`
+	fn start_server() -> (Server, u16) {
+		let mut config = ServerConfig::new(NoClientAuth::new());
+		let certs = pemfile::certs(&mut open(&fixture("server.pem")).unwrap()).unwrap();
+		config.set_single_cert(certs, load_key(&fixture("server.key")).unwrap()).unwrap();
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let server = HttpServer::new(|| App::new().route("/", web::get().to(HttpResponse::Ok)))
+			.workers(1)
+			.listen_rustls(listener, config)
+			.unwrap()
+			.run();
+		(server, port)
+	}
+
+	async fn request(options: TlsOptions, port: u16) -> Result<u16, String> {
+		let client = HTTPClient::new().config(HttpConfig {
+			retries: 0,
+			tls: Some(std::sync::Arc::new(options.build()?)),
+			..HttpConfig::default()
+		});
+		client.request(RequestInfo::get(format!("https://localhost:{}/", port))).await.map(|res| res.status_code)
+	}
+
+	#[actix_rt::test]
+	async fn trusts_the_ca_bundle() {
+		let (server, port) = start_server();
+		let options = TlsOptions { ca_bundle: Some(fixture("ca.pem")), ..TlsOptions::default() };
+		assert_eq!(request(options, port).await, Ok(200));
+		server.stop(false).await;
+	}
+
+	#[actix_rt::test]
+	async fn rejects_other_cas() {
+		let (server, port) = start_server();
+		let other = TlsOptions { ca_bundle: Some(fixture("other-ca.pem")), ..TlsOptions::default() };
+		assert!(request(other, port).await.is_err());
+		// The public roots don't know the test CA either
+		assert!(request(TlsOptions::default(), port).await.is_err());
+		server.stop(false).await;
+	}
+
+	#[cfg(debug_assertions)]
+	#[actix_rt::test]
+	async fn insecure_skips_verification_in_debug_builds() {
+		let (server, port) = start_server();
+		let options = TlsOptions { ca_bundle: Some(fixture("other-ca.pem")), insecure: true, ..TlsOptions::default() };
+		assert_eq!(request(options, port).await, Ok(200));
+		server.stop(false).await;
+	}
+
+	#[cfg(not(debug_assertions))]
+	#[test]
+	fn insecure_is_refused_in_release_builds() {
+		let options = TlsOptions { insecure: true, ..TlsOptions::default() };
+		assert!(options.build().is_err());
+	}
+
+	#[test]
+	fn rejects_empty_ca_bundles() {
+		let options = TlsOptions { ca_bundle: Some(fixture("server.key")), ..TlsOptions::default() };
+		assert!(options.build().is_err());
+	}
+}
@@ -1,25 +1,37 @@
 // Taken from Quartz, thanks Cassy <3
 
-use std::u128;
-use std::fmt::{self, Display, Formatter};
+use std::fmt::{self, Debug, Display, Formatter};
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use rand::prelude::*;
+use crate::util::base62;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
 
 const VERSION_VARIANT_MASK: u128 = 0xFFFFFFFF_FFFF_0FFF_3FFF_FFFFFFFFFFFF;
 const VERSION_4_VARIANT_1: u128  = 0x00000000_0000_4000_8000_000000000000;
+const VERSION_7_VARIANT_1: u128  = 0x00000000_0000_7000_8000_000000000000;
 
-/// Represents a 128-bit (16-byte) universally unique identifier (UUID). This struct only
-/// supports version 4, variant 1 UUIDs (IE randomly generated UUIDs).
+/// Represents a 128-bit (16-byte) universally unique identifier (UUID). This struct supports
+/// version 4, variant 1 UUIDs (IE randomly generated UUIDs) and version 7, variant 1 UUIDs
+/// (IE time ordered UUIDs).
+///
+/// UUIDs are serialized as hyphenated strings, which is also what documents use as their id.
 #[repr(transparent)]
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Uuid(u128);
 
 impl Uuid {
-    /// Apply the correct version and variant to the UUID.
+    /// Apply the correct version and variant to the UUID. Version 7 UUIDs are left alone,
+    /// everything else becomes version 4.
     #[inline(always)]
     fn correct_version(uuid: u128) -> u128 {
-        (uuid & VERSION_VARIANT_MASK) | VERSION_4_VARIANT_1
+        if uuid & !VERSION_VARIANT_MASK == VERSION_7_VARIANT_1 {
+            uuid
+        } else {
+            (uuid & VERSION_VARIANT_MASK) | VERSION_4_VARIANT_1
+        }
     }
 
     /// Create a random UUID. Since the UUID must contain version and variant info, only 122
@@ -36,6 +48,37 @@ impl Uuid {
         Uuid(Self::correct_version(rand::thread_rng().gen()))
     }
 
+    /// Create a version 7 UUID. The first 48 bits are the milliseconds since the unix epoch and
+    /// the rest is random, so UUIDs created later sort after earlier ones (down to the millisecond).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use util::Uuid;
+    /// let first = Uuid::time_ordered();
+    /// std::thread::sleep(std::time::Duration::from_millis(1));
+    /// assert!(Uuid::time_ordered() > first);
+    /// ```
+    pub fn time_ordered() -> Self {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis()).unwrap_or(0);
+        let random: u128 = rand::thread_rng().gen();
+        Uuid(((millis & 0xFFFF_FFFFFFFF) << 80) | (random & 0xFFFF_FFFFFFFFFFFFFFFFFFFF & VERSION_VARIANT_MASK) | VERSION_7_VARIANT_1)
+    }
+
+    /// The version of this UUID, 4 for random UUIDs and 7 for time ordered ones.
+    pub fn version(&self) -> u8 {
+        ((self.0 >> 76) & 0xF) as u8
+    }
+
+    /// The milliseconds since the unix epoch a time ordered UUID was created at, `None` for random UUIDs.
+    pub fn timestamp_millis(&self) -> Option<u64> {
+        if self.version() == 7 {
+            Some((self.0 >> 80) as u64)
+        } else {
+            None
+        }
+    }
+
     /// Converts the given bytes (big-endian) into a UUID and applies the correct version and variant information.
     /// This function will return an error if the given slice is not 16 bytes long.
     /// 
@@ -54,11 +97,9 @@ impl Uuid {
         assert_eq!(bytes.len(), 16, "Expected 16 bytes.");
 
         let mut inner: u128 = 0;
-        for i in 0..15 {
-            inner |= bytes[i] as u128;
-            inner <<= 8;
+        for byte in bytes {
+            inner = (inner << 8) | *byte as u128;
         }
-        inner |= bytes[15] as u128;
 
         Uuid(Self::correct_version(inner))
     }
@@ -81,11 +122,9 @@ impl Uuid {
         assert_eq!(bytes.len(), 16, "Expected 16 bytes.");
 
         let mut inner: u128 = 0;
-        for i in 1..16 {
-            inner |= bytes[16 - i] as u128;
-            inner <<= 8;
+        for byte in bytes.iter().rev() {
+            inner = (inner << 8) | *byte as u128;
         }
-        inner |= bytes[0] as u128;
 
         Uuid(Self::correct_version(inner))
     }
@@ -116,6 +155,32 @@ impl Uuid {
         self.0 as u64
     }
 
+    /// A 22 character base62 code for this UUID, for use in links where the hyphenated form is too long.
+    pub fn to_short_code(&self) -> String {
+        base62::encode_u128(self.0)
+    }
+
+    /// Turns a code from `to_short_code` back into a UUID. Returns `None` if the code isn't valid base62
+    /// or doesn't hold a version 4 or 7 UUID.
+    pub fn from_short_code(code: &str) -> Option<Self> {
+        let inner = base62::decode_u128(code)?;
+        if inner != Self::correct_version(inner) {
+            return None;
+        }
+        Some(Uuid(inner))
+    }
+
+    /// Parses a document id, only accepting ids that are exactly what this UUID prints as. Unlike
+    /// `from_str` this won't quietly fix up ids that CouchDB generated.
+    pub fn from_document_id(id: &str) -> Option<Self> {
+        let uuid = Uuid::from_str(id).ok()?;
+        if uuid.to_string() == id {
+            Some(uuid)
+        } else {
+            None
+        }
+    }
+
     /// Returns the inner u128 composing this UUID.
     pub fn as_u128(&self) -> u128 {
         self.0
@@ -147,7 +212,7 @@ impl FromStr for Uuid {
     /// assert!(Uuid::from_str("invalid").is_err());
     /// ```
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let raw = s.to_owned().replace("-", "");
+        let raw = s.replace('-', "");
         if raw.len() != 32 {
             return Err("Expected condensed string to have length 32.");
         }
@@ -171,4 +236,54 @@ impl Display for Uuid {
             self.0 & 0xFFFFFFFFFFFF
         )
     }
-}
\ No newline at end of file
+}
+
+impl Debug for Uuid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Serialize for Uuid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Uuid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Uuid::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_codes_round_trip() {
+        for uuid in &[Uuid::random(), Uuid::time_ordered()] {
+            let code = uuid.to_short_code();
+            assert_eq!(code.len(), base62::U128_LENGTH);
+            assert_eq!(Uuid::from_short_code(&code), Some(*uuid));
+        }
+    }
+
+    #[test]
+    fn short_codes_only_hold_v4_or_v7() {
+        // Version 1 and the nil UUID are valid base62 but not UUIDs we make
+        let v1 = 0x7919a79b_b256_1782_bf36_13990ca65bb7_u128;
+        assert_eq!(Uuid::from_short_code(&base62::encode_u128(v1)), None);
+        assert_eq!(Uuid::from_short_code(&base62::encode_u128(0)), None);
+        // Wrong variant
+        let variant = 0x7919a79b_b256_4782_3f36_13990ca65bb7_u128;
+        assert_eq!(Uuid::from_short_code(&base62::encode_u128(variant)), None);
+    }
+
+    #[test]
+    fn short_codes_reject_garbage() {
+        assert_eq!(Uuid::from_short_code(""), None);
+        assert_eq!(Uuid::from_short_code("not-a-short-code!"), None);
+    }
+}